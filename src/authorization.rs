@@ -0,0 +1,176 @@
+use url::Url;
+
+use crate::{pkce::PkcePair, Error};
+
+/// Builds the URL of an authorization request, see
+/// [RFC 6749, section 4.1.1](https://www.rfc-editor.org/rfc/rfc6749#section-4.1.1).
+///
+/// All parameters are percent-encoded and appended to the query parameters the endpoint may already have.
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    endpoint: Url,
+    response_type: String,
+    client_id: String,
+    redirect_uri: Option<String>,
+    scopes: Vec<String>,
+    state: Option<String>,
+    code_challenge: Option<(String, String)>,
+    params: Vec<(String, String)>,
+}
+
+impl AuthorizationRequest {
+    /// Creates a request for the authorization code flow.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if `authorization_endpoint` is not a valid URL.
+    pub fn new(authorization_endpoint: &str, client_id: impl Into<String>) -> Result<Self, Error> {
+        Ok(Self::from_endpoint(
+            parse_endpoint(authorization_endpoint)?,
+            client_id.into(),
+        ))
+    }
+
+    pub(crate) fn from_endpoint(endpoint: Url, client_id: String) -> Self {
+        Self {
+            endpoint,
+            response_type: "code".to_string(),
+            client_id,
+            redirect_uri: None,
+            scopes: Vec::new(),
+            state: None,
+            code_challenge: None,
+            params: Vec::new(),
+        }
+    }
+
+    /// Sets the `response_type`.
+    ///
+    /// Default: `code`.
+    #[must_use]
+    pub fn response_type(mut self, response_type: impl Into<String>) -> Self {
+        self.response_type = response_type.into();
+        self
+    }
+
+    /// Sets the `redirect_uri`, for example [`ServerHandle::redirect_uri`](crate::ServerHandle::redirect_uri).
+    #[must_use]
+    pub fn redirect_uri(mut self, redirect_uri: impl Into<String>) -> Self {
+        self.redirect_uri = Some(redirect_uri.into());
+        self
+    }
+
+    /// Sets the scopes to request. They are sent space-separated in the `scope` parameter.
+    #[must_use]
+    pub fn scopes<I: IntoIterator<Item = S>, S: Into<String>>(mut self, scopes: I) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the `state`, for example [`OauthConfig::generate_state`](crate::OauthConfig::generate_state).
+    #[must_use]
+    pub fn state(mut self, state: impl Into<String>) -> Self {
+        self.state = Some(state.into());
+        self
+    }
+
+    /// Sets the `code_challenge` and `code_challenge_method` of a [`PkcePair`].
+    #[must_use]
+    pub fn pkce(self, pkce: &PkcePair) -> Self {
+        self.code_challenge(pkce.challenge(), pkce.method())
+    }
+
+    /// Sets the `code_challenge` and `code_challenge_method`, for challenges not created with [`PkcePair`].
+    #[must_use]
+    pub fn code_challenge(
+        mut self,
+        challenge: impl Into<String>,
+        method: impl Into<String>,
+    ) -> Self {
+        self.code_challenge = Some((challenge.into(), method.into()));
+        self
+    }
+
+    /// Adds an extra query parameter, for example `prompt=consent`.
+    #[must_use]
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    /// Builds the URL to open in the user's browser.
+    #[must_use]
+    pub fn build(&self) -> Url {
+        let mut url = self.endpoint.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", &self.response_type)
+                .append_pair("client_id", &self.client_id);
+            if let Some(redirect_uri) = &self.redirect_uri {
+                query.append_pair("redirect_uri", redirect_uri);
+            }
+            if let Some(state) = &self.state {
+                query.append_pair("state", state);
+            }
+            if !self.scopes.is_empty() {
+                query.append_pair("scope", &self.scopes.join(" "));
+            }
+            if let Some((challenge, method)) = &self.code_challenge {
+                query
+                    .append_pair("code_challenge", challenge)
+                    .append_pair("code_challenge_method", method);
+            }
+            query.extend_pairs(&self.params);
+        }
+        url
+    }
+}
+
+pub(crate) fn parse_endpoint(endpoint: &str) -> Result<Url, Error> {
+    Url::parse(endpoint)
+        .map_err(|err| Error::InvalidConfig(format!("Invalid endpoint {:?}: {}", endpoint, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters_are_encoded() {
+        let url = AuthorizationRequest::new("https://example.com/authorize?audience=api", "app")
+            .unwrap()
+            .redirect_uri("http://127.0.0.1:1234/callback?app=desktop")
+            .scopes(["openid", "profile email"])
+            .state("a&b")
+            .param("claims", "x=1&y=2#z")
+            .build();
+
+        assert_eq!(url.fragment(), None);
+        let query = url.query().unwrap();
+        assert!(query.contains("scope=openid+profile+email"), "{}", query);
+        assert!(
+            query.contains("redirect_uri=http%3A%2F%2F127.0.0.1%3A1234%2Fcallback%3Fapp%3Ddesktop"),
+            "{}",
+            query
+        );
+
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        let expected = [
+            ("audience", "api"),
+            ("response_type", "code"),
+            ("client_id", "app"),
+            ("redirect_uri", "http://127.0.0.1:1234/callback?app=desktop"),
+            ("state", "a&b"),
+            ("scope", "openid profile email"),
+            ("claims", "x=1&y=2#z"),
+        ];
+        assert_eq!(
+            pairs,
+            expected
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
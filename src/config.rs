@@ -0,0 +1,425 @@
+use std::{
+    borrow::Cow, collections::HashMap, fmt, net::IpAddr, path::PathBuf, sync::Arc, time::Duration,
+};
+
+use tauri::{AppHandle, Runtime};
+
+use crate::{error, random_token, Error, OauthResponse};
+
+/// The optional server config.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct OauthConfig {
+    /// An array of hard-coded ports the server should try to bind to.
+    /// The ports are tried in order and the server binds to the first one that is free.
+    /// This should only be used if your oauth provider does not accept wildcard localhost addresses.
+    ///
+    /// Default: Asks the system for a free port.
+    pub ports: Option<Vec<u16>>,
+    /// The IP address the server should bind to, for example `::1` for IPv6-only environments.
+    /// Binding to an unspecified address like `0.0.0.0` makes the server reachable from other machines
+    /// and should only be used if the browser can't reach the loopback interface, for example in WSL2 or containers.
+    /// Connections from other machines are rejected unless `allow_remote_peers` is enabled.
+    ///
+    /// Default: `127.0.0.1`.
+    pub address: Option<IpAddr>,
+    /// If `address` is a loopback address, also listen on the loopback address of the other IP family on the same port,
+    /// for example `::1` next to `127.0.0.1`. Some browsers resolve `localhost` to `::1` first, so this keeps
+    /// `localhost` redirect URIs working. If the other address can't be bound, the server only listens on `address`.
+    ///
+    /// Default: `true`.
+    pub dual_stack: Option<bool>,
+    /// Optional static html string send to the user after being redirected.
+    /// Keep it self-contained and as small as possible.
+    ///
+    /// `{{error}}`, `{{error_description}}` and `{{state}}` are replaced with the HTML-escaped parameters of the callback.
+    /// This only works for parameters in the query or a `form_post` body, because the browser doesn't send the fragment
+    /// to the server. The same applies to `error_response`.
+    ///
+    /// Default: `"<html><body>Please return to the app.</body></html>"`.
+    pub response: Option<Cow<'static, str>>,
+    /// Optional html string send instead of `response` if the callback contains an `error` parameter,
+    /// for example because the user denied access.
+    ///
+    /// Default: `"<html><body>Login failed: {{error}}. Please return to the app.</body></html>"`.
+    pub error_response: Option<Cow<'static, str>>,
+    /// Localized versions of `response` by language tag, for example `de` or `pt-BR`.
+    /// The page is picked by the browser's `Accept-Language` header. Exact matches are preferred,
+    /// otherwise the primary language is used, so `de` is also served to `de-AT` browsers.
+    ///
+    /// Default: `response` is served to all browsers.
+    pub localized_responses: Option<HashMap<String, Cow<'static, str>>>,
+    /// Localized versions of `error_response`, see `localized_responses`.
+    ///
+    /// Default: `error_response` is served to all browsers.
+    pub localized_error_responses: Option<HashMap<String, Cow<'static, str>>>,
+    /// Optional path to an html file used as `response`. The file is read once when the server starts.
+    /// Only settable from Rust or the `plugins.oauth` section of tauri.conf.json, the plugin's `start` command ignores it
+    /// so the webview can't make the server read arbitrary files. Use `response_asset` there instead.
+    ///
+    /// Default: `response` is used as is.
+    #[serde(skip)]
+    pub response_file: Option<PathBuf>,
+    /// Optional path of one of the app's frontend assets, like `oauth.html`, used as `response` by the plugin's `start` command.
+    /// In Rust, use [`OauthConfig::load_asset_response`] instead.
+    ///
+    /// Default: `response` is used as is.
+    pub response_asset: Option<String>,
+    /// Additional files served by the server, keyed by their path like `/assets/logo.png`, so the response page can reference them.
+    /// See [`OauthConfig::add_resource`] and [`OauthConfig::load_asset_resource`].
+    ///
+    /// Default: No additional files.
+    #[serde(skip)]
+    pub resources: Option<HashMap<String, Resource>>,
+    /// How the server answers the callback request, see [`ResponseMode`].
+    ///
+    /// Default: [`ResponseMode::Page`].
+    pub response_mode: Option<ResponseMode>,
+    /// How the server captures the callback parameters, see [`CaptureMode`].
+    ///
+    /// Default: [`CaptureMode::Script`].
+    pub capture: Option<CaptureMode>,
+    /// Try to close the browser tab after the callback was captured. Most browsers only allow this for tabs
+    /// opened by a script, otherwise the page is replaced with a "You can close this tab now." hint.
+    ///
+    /// Default: `false`.
+    pub close_tab: Option<bool>,
+    /// Optional custom URL scheme target, for example `"myapp://callback"`, the response page redirects to
+    /// after the callback was captured. The query and fragment parameters of the callback are re-encoded
+    /// and appended to it, so the OS deep-link handler receives the same values as the `handler` closure.
+    ///
+    /// This is meant for apps migrating from loopback redirects to custom URL schemes.
+    /// Note that the loopback server still captures the callback, which means your app will see the same
+    /// parameters twice: once in `handler` and once through the deep link. Use the `state` parameter to
+    /// dedupe them, i.e. process whichever arrives first and ignore the second one.
+    ///
+    /// Default: No redirect.
+    pub bridge: Option<Cow<'static, str>>,
+    /// Optional extra headers added to the response page, for example a `Content-Security-Policy`.
+    /// Header values must not contain line breaks, and `Content-Length` is always set by the server.
+    /// `Content-Type` and `Cache-Control` default to `text/html; charset=utf-8` and `no-store` unless they are set here.
+    ///
+    /// Default: No extra headers.
+    pub headers: Option<HashMap<String, String>>,
+    /// The `state` value sent with the authorization request. If set, the server compares it to the `state`
+    /// parameter of the callback and answers with a 400 page instead of executing the handler if they don't match.
+    /// The server keeps listening after a mismatch so a spoofed request can't abort the real flow.
+    ///
+    /// Use [`OauthConfig::generate_state`] to generate a random value.
+    ///
+    /// Default: The `state` parameter is not verified.
+    pub state: Option<String>,
+    /// Serve the callback over HTTPS using an ephemeral self-signed certificate, for providers that refuse `http://` redirect URIs.
+    /// Browsers will show a certificate warning the user has to accept, see [`ServerHandle::certificate_fingerprint`].
+    ///
+    /// Default: `false`.
+    #[cfg(feature = "tls")]
+    pub tls: Option<bool>,
+    /// Executed for errors that happen while the server is running, for example failed reads or writes of a connection
+    /// or a panicking handler. The server keeps listening after these errors. They are always logged as well.
+    ///
+    /// Default: The errors are only logged.
+    #[serde(skip)]
+    pub on_error: Option<ErrorHandler>,
+    /// The maximum size of a request in bytes, including its headers and the body of `form_post` callbacks.
+    /// Larger requests are answered with a 413 status code.
+    ///
+    /// Default: 64 KiB.
+    pub max_request_size: Option<usize>,
+    /// How long the server waits for the request of a connection before dropping it, so a stalled client,
+    /// like a browser preconnect that never sends a request, can't block the server.
+    /// This applies to the whole request, not just to each read, so clients sending it slowly are dropped too.
+    /// Deserialized from milliseconds.
+    ///
+    /// Default: 5 seconds.
+    #[serde(default, deserialize_with = "deserialize_millis")]
+    pub read_timeout: Option<Duration>,
+    /// How long the server waits for a client to accept the response before dropping the connection.
+    /// Deserialized from milliseconds.
+    ///
+    /// Default: 5 seconds.
+    #[serde(default, deserialize_with = "deserialize_millis")]
+    pub write_timeout: Option<Duration>,
+    /// How long the handler of [`start_with_async_response`](crate::start_with_async_response) may take,
+    /// for example for the token exchange, before the browser gets the [`OauthConfig::error_response`] page
+    /// and the handler is reported as [`Error::HandlerFailed`]. Deserialized from milliseconds.
+    ///
+    /// Default: 30 seconds.
+    #[serde(default, deserialize_with = "deserialize_millis")]
+    pub handler_timeout: Option<Duration>,
+    /// Stops the server if no callback arrived in time, for example because the user abandoned the login.
+    /// [`OauthConfig::on_timeout`] is executed afterwards and [`PendingCallback`](crate::PendingCallback) resolves to [`Error::Timeout`].
+    /// Deserialized from milliseconds.
+    ///
+    /// Default: The server runs until it received a callback or was stopped.
+    #[serde(default, deserialize_with = "deserialize_millis")]
+    pub auto_cancel_after: Option<Duration>,
+    /// Executed after the server stopped because of [`OauthConfig::auto_cancel_after`].
+    ///
+    /// Default: Nothing is executed.
+    #[serde(skip)]
+    pub on_timeout: Option<TimeoutHandler>,
+    /// Keep the server running after a callback until it's stopped. Set [`OauthConfig::allow_repeated_callbacks`]
+    /// to execute the handler for every callback, for example to log in multiple accounts one after another.
+    /// Note that the same `state` value is expected for all callbacks if it's set.
+    /// [`start_async`](crate::start_async) and [`start_once`](crate::start_once) ignore this because they can only handle one callback.
+    ///
+    /// Default: `false`.
+    pub persistent: Option<bool>,
+    /// Execute the handler for every callback a [`persistent`](OauthConfig::persistent) server receives.
+    /// Otherwise callbacks after the first accepted one, for example because the user reloaded the tab
+    /// or a replayed redirect, are answered with a 410 status code and reported as [`Error::CallbackReplayed`].
+    ///
+    /// Default: `false`.
+    pub allow_repeated_callbacks: Option<bool>,
+    /// The path of the `redirect_uri`, for example `/callback`. Requests to other paths, like the browser's
+    /// `/favicon.ico` request, are answered with a 404 status code and never reach the handler.
+    /// [`ServerHandle::redirect_uri`](crate::ServerHandle::redirect_uri) includes this path.
+    ///
+    /// Default: `/`.
+    pub callback_path: Option<String>,
+    /// Appends a random segment to the `callback_path`, for example `/callback/3f9a…`, so other local processes
+    /// can't guess the callback URL. Always use [`ServerHandle::redirect_uri`](crate::ServerHandle::redirect_uri) to get the full path.
+    ///
+    /// Default: `false`.
+    pub secret_path: Option<bool>,
+    /// Serve `/health` with a JSON object containing the plugin `version`, the `callback_path` the server expects
+    /// and whether a callback was received (`callback_received`), so users can check a stuck login by opening one URL.
+    /// The random segment of [`OauthConfig::secret_path`] is replaced with `[secret]`.
+    ///
+    /// Default: `false`.
+    pub health_endpoint: Option<bool>,
+    /// Accept connections from non-loopback addresses. Only enable this together with a non-loopback `address`,
+    /// for example in WSL2 where the browser connects from the Windows host. Other connections are answered with a 403 status code.
+    ///
+    /// Default: `false`.
+    pub allow_remote_peers: Option<bool>,
+    /// Decides whether a request is accepted based on its `Host` and `Origin` headers, rejected requests are
+    /// answered with a 403 status code before the handler runs. The `Host` is empty if the request didn't contain one.
+    ///
+    /// Default: Accepts `localhost`, loopback addresses and the configured `address`, or any host if that's unspecified.
+    #[serde(skip)]
+    pub host_validator: Option<HostValidator>,
+    /// Checks callbacks before the handler runs, for example the `state` or the `iss` parameter.
+    /// Rejected callbacks are answered with the `error_response` page and a 400 status code, their `{{error}}`
+    /// and `{{error_description}}` are the ones of the [`RejectReason`]. The handler is not executed and the server keeps listening.
+    ///
+    /// Default: Every callback is passed to the handler.
+    #[serde(skip)]
+    pub validator: Option<Validator>,
+}
+
+pub(crate) fn deserialize_millis<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    let millis: Option<u64> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(millis.map(Duration::from_millis))
+}
+
+pub(crate) fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// A callback for errors of a running server, see [`OauthConfig::on_error`].
+#[derive(Clone)]
+pub struct ErrorHandler(pub(crate) Arc<dyn Fn(Error) + Send + Sync>);
+
+impl ErrorHandler {
+    /// Wraps `handler` so it can be stored in an [`OauthConfig`].
+    pub fn new<F: Fn(Error) + Send + Sync + 'static>(handler: F) -> Self {
+        Self(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorHandler")
+    }
+}
+
+type HostValidatorFn = dyn Fn(&str, Option<&str>) -> bool + Send + Sync;
+
+/// Validates the `Host` and `Origin` headers of requests, see [`OauthConfig::host_validator`].
+#[derive(Clone)]
+pub struct HostValidator(pub(crate) Arc<HostValidatorFn>);
+
+impl HostValidator {
+    /// Wraps `validator`, which receives the `Host` and `Origin` headers, so it can be stored in an [`OauthConfig`].
+    pub fn new<F: Fn(&str, Option<&str>) -> bool + Send + Sync + 'static>(validator: F) -> Self {
+        Self(Arc::new(validator))
+    }
+}
+
+impl fmt::Debug for HostValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HostValidator")
+    }
+}
+
+type ValidatorFn = dyn Fn(&OauthResponse) -> Result<(), RejectReason> + Send + Sync;
+
+/// Checks callbacks before the handler runs, see [`OauthConfig::validator`].
+#[derive(Clone)]
+pub struct Validator(pub(crate) Arc<ValidatorFn>);
+
+impl Validator {
+    /// Wraps `validator` so it can be stored in an [`OauthConfig`].
+    pub fn new<F: Fn(&OauthResponse) -> Result<(), RejectReason> + Send + Sync + 'static>(
+        validator: F,
+    ) -> Self {
+        Self(Arc::new(validator))
+    }
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Validator")
+    }
+}
+
+/// Why a [`Validator`] rejected a callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectReason {
+    /// Shown as `{{error}}` on the error page, for example `invalid_request`.
+    pub error: String,
+    /// Shown as `{{error_description}}` on the error page.
+    pub description: Option<String>,
+}
+
+impl RejectReason {
+    /// Creates a reason without a description.
+    pub fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            description: None,
+        }
+    }
+
+    /// Sets the description.
+    #[must_use]
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&error::describe(&self.error, self.description.as_deref()))
+    }
+}
+
+/// A callback for servers that stopped because of [`OauthConfig::auto_cancel_after`].
+#[derive(Clone)]
+pub struct TimeoutHandler(pub(crate) Arc<dyn Fn() + Send + Sync>);
+
+impl TimeoutHandler {
+    /// Wraps `handler` so it can be stored in an [`OauthConfig`].
+    pub fn new<F: Fn() + Send + Sync + 'static>(handler: F) -> Self {
+        Self(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for TimeoutHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TimeoutHandler")
+    }
+}
+
+impl OauthConfig {
+    /// Generates a random `state` value, stores it in the config and returns it so it can be added to the authorization URL.
+    pub fn generate_state(&mut self) -> &str {
+        self.state.insert(random_token())
+    }
+
+    /// Uses one of the app's frontend assets, for example `oauth.html`, as the `response` page.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if the asset doesn't exist or isn't valid UTF-8.
+    pub fn load_asset_response<R: Runtime>(
+        &mut self,
+        app: &AppHandle<R>,
+        path: &str,
+    ) -> Result<(), Error> {
+        let asset = app
+            .asset_resolver()
+            .get(path.to_string())
+            .ok_or_else(|| Error::InvalidConfig(format!("Asset {:?} not found", path)))?;
+        let page = String::from_utf8(asset.bytes)
+            .map_err(|_| Error::InvalidConfig(format!("Asset {:?} is not valid UTF-8", path)))?;
+        self.response = Some(page.into());
+        Ok(())
+    }
+
+    /// Serves one of the app's frontend assets, for example `/assets/logo.png`, at the same path.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if the asset doesn't exist.
+    pub fn load_asset_resource<R: Runtime>(
+        &mut self,
+        app: &AppHandle<R>,
+        path: &str,
+    ) -> Result<(), Error> {
+        let asset = app
+            .asset_resolver()
+            .get(path.to_string())
+            .ok_or_else(|| Error::InvalidConfig(format!("Asset {:?} not found", path)))?;
+        let path = format!("/{}", path.trim_start_matches('/'));
+        self.add_resource(
+            path,
+            Resource {
+                content_type: asset.mime_type.into(),
+                body: asset.bytes.into(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Serves `resource` at `path`, which must start with a slash.
+    pub fn add_resource(&mut self, path: impl Into<String>, resource: Resource) {
+        self.resources
+            .get_or_insert_with(HashMap::new)
+            .insert(path.into(), resource);
+    }
+}
+
+/// How the server answers the callback request.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    /// Serve the `response` page, see [`OauthConfig::response`].
+    #[default]
+    Page,
+    /// Redirect the browser to this URL, for example a custom URL scheme or a "login complete" page on your website.
+    /// `{{code}}`, `{{state}}`, `{{error}}` and `{{error_description}}` are replaced with the URL-encoded callback parameters.
+    ///
+    /// Callbacks with the parameters in the fragment still get the page, because it's needed to capture the fragment.
+    Redirect(String),
+}
+
+/// How the server captures the callback parameters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureMode {
+    /// Serve a page whose script sends the full URL, including the fragment, back to the server.
+    #[default]
+    Script,
+    /// Use the URL of the request directly and execute the handler right away, without the extra request of [`CaptureMode::Script`].
+    /// This also works in browsers with JavaScript disabled, but only for providers that return the parameters in the query.
+    ///
+    /// Requests without a query still get the page, because the parameters can only be in the fragment then.
+    QueryOnly,
+}
+
+/// A file served by the server, see [`OauthConfig::resources`].
+#[derive(Debug, Clone)]
+pub struct Resource {
+    /// The `Content-Type` header, for example `image/png`.
+    pub content_type: Cow<'static, str>,
+    /// The file's content.
+    pub body: Cow<'static, [u8]>,
+}
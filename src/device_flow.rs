@@ -0,0 +1,289 @@
+//! The device authorization grant ([RFC 8628](https://www.rfc-editor.org/rfc/rfc8628)).
+//!
+//! Instead of redirecting to the localhost server, the user enters a short code on the provider's website,
+//! possibly on another device, while the app polls the token endpoint. No server is started for this flow.
+
+use std::time::{Duration, Instant};
+
+use tauri::{AppHandle, Manager, Runtime};
+use url::Url;
+
+use crate::{
+    authorization::parse_endpoint,
+    flow::{post_token_request, TokenError},
+    providers::Provider,
+    Error, TokenResponse,
+};
+
+/// How much a `slow_down` response increases the polling interval, see RFC 8628 section 3.5.
+const SLOW_DOWN: Duration = Duration::from_secs(5);
+
+/// The device code flow for a client registered with the provider.
+#[derive(Debug, Clone)]
+pub struct DeviceFlow {
+    client_id: String,
+    client_secret: Option<String>,
+    device_authorization_endpoint: Url,
+    token_endpoint: Url,
+    scopes: Vec<String>,
+}
+
+impl DeviceFlow {
+    /// Creates a new device code flow.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if one of the endpoints is not a valid URL.
+    pub fn new(
+        client_id: impl Into<String>,
+        device_authorization_endpoint: &str,
+        token_endpoint: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            client_id: client_id.into(),
+            client_secret: None,
+            device_authorization_endpoint: parse_endpoint(device_authorization_endpoint)?,
+            token_endpoint: parse_endpoint(token_endpoint)?,
+            scopes: Vec::new(),
+        })
+    }
+
+    /// Creates a new device code flow using the endpoints and default scopes of a [`Provider`].
+    /// Returns `None` if the provider doesn't support the device code flow.
+    #[must_use]
+    pub fn from_provider(provider: &Provider, client_id: impl Into<String>) -> Option<Self> {
+        let flow = Self::new(
+            client_id,
+            provider.device_authorization_endpoint?,
+            provider.token_endpoint,
+        )
+        .ok()?;
+
+        Some(flow.scopes(provider.default_scopes.iter().copied()))
+    }
+
+    /// Sets the client secret, for providers that require one.
+    #[must_use]
+    pub fn client_secret(mut self, client_secret: impl Into<String>) -> Self {
+        self.client_secret = Some(client_secret.into());
+        self
+    }
+
+    /// Sets the scopes to request.
+    #[must_use]
+    pub fn scopes<I: IntoIterator<Item = S>, S: Into<String>>(mut self, scopes: I) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Requests a device and user code. Show [`DeviceAuthorization::user_code`] and
+    /// [`DeviceAuthorization::verification_uri`] to the user, then call [`DeviceFlow::poll`].
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::TokenRequest`] with the provider's error code if it rejected the request,
+    ///   for example `invalid_client` or `invalid_scope`.
+    /// - Returns [`Error::Http`] if the request fails and [`Error::InvalidResponse`] if the response is invalid.
+    pub async fn request(&self) -> Result<DeviceAuthorization, Error> {
+        let scope = self.scopes.join(" ");
+        let mut form = vec![("client_id", self.client_id.as_str())];
+        if !scope.is_empty() {
+            form.push(("scope", &scope));
+        }
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let response = reqwest::Client::new()
+            .post(self.device_authorization_endpoint.clone())
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&form)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+
+        if !status.is_success() {
+            return Err(match serde_json::from_slice::<TokenError>(&body) {
+                Ok(err) => device_error(err),
+                Err(_) => Error::TokenRequest {
+                    error: status.to_string(),
+                    description: None,
+                },
+            });
+        }
+        serde_json::from_slice(&body).map_err(|err| Error::InvalidResponse(err.to_string()))
+    }
+
+    /// Runs the whole flow: Requests the codes, emits them to all windows via the `oauth://device-code` event
+    /// with a [`DeviceAuthorization`] payload, so the frontend can show the code and the verification page,
+    /// and polls the token endpoint until the user approved the request.
+    ///
+    /// # Errors
+    ///
+    /// - Returns the errors of [`DeviceFlow::request`] and [`DeviceFlow::poll`].
+    pub async fn run<R: Runtime>(&self, app: &AppHandle<R>) -> Result<TokenResponse, Error> {
+        let authorization = self.request().await?;
+        if let Err(emit_err) = app.emit_all("oauth://device-code", authorization.clone()) {
+            error!("Error emitting oauth://device-code event: {}", emit_err);
+        }
+        self.poll(&authorization).await
+    }
+
+    /// Polls the token endpoint until the user approved the request, honoring the provider's
+    /// polling interval and `slow_down` responses.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::Timeout`] if the device code expired.
+    /// - Returns [`Error::Authorization`] if the user denied the request.
+    /// - Returns [`Error::TokenRequest`] if the provider returned another error
+    ///   and [`Error::Http`] or [`Error::InvalidResponse`] if a request fails.
+    pub async fn poll(&self, authorization: &DeviceAuthorization) -> Result<TokenResponse, Error> {
+        let deadline = Instant::now() + Duration::from_secs(authorization.expires_in);
+        let mut polling = Polling {
+            interval: Duration::from_secs(authorization.interval),
+        };
+
+        let mut form = vec![
+            ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ("device_code", authorization.device_code.as_str()),
+            ("client_id", self.client_id.as_str()),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        loop {
+            if Instant::now() + polling.interval > deadline {
+                return Err(Error::Timeout);
+            }
+            tokio::time::sleep(polling.interval).await;
+
+            let response = post_token_request(&self.token_endpoint, &form).await?;
+            if let Some(result) = polling.handle(response) {
+                return result;
+            }
+        }
+    }
+}
+
+/// The state of [`DeviceFlow::poll`] between two requests.
+struct Polling {
+    interval: Duration,
+}
+
+impl Polling {
+    /// Handles a response of the token endpoint. Returns the result once the flow completed or failed,
+    /// or `None` if the token endpoint should be polled again.
+    fn handle(
+        &mut self,
+        response: Result<TokenResponse, TokenError>,
+    ) -> Option<Result<TokenResponse, Error>> {
+        match response {
+            Ok(tokens) => Some(Ok(tokens)),
+            Err(err) => match err.error.as_str() {
+                "authorization_pending" => None,
+                "slow_down" => {
+                    self.interval += SLOW_DOWN;
+                    None
+                }
+                _ => Some(Err(device_error(err))),
+            },
+        }
+    }
+}
+
+/// Maps the errors of the device flow with a meaning of their own, the rest become [`Error::TokenRequest`].
+fn device_error(err: TokenError) -> Error {
+    match err.error.as_str() {
+        "expired_token" => Error::Timeout,
+        "access_denied" => Error::Authorization {
+            error: err.error,
+            description: err.error_description,
+        },
+        _ => err.into(),
+    }
+}
+
+/// The response of the device authorization endpoint.
+///
+/// This can be sent to the frontend as-is to display the code, the `device_code` is not serialized.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DeviceAuthorization {
+    /// The code used to poll the token endpoint.
+    #[serde(skip_serializing)]
+    pub device_code: String,
+    /// The code the user has to enter on the verification page.
+    pub user_code: String,
+    /// The verification page. Some providers, like Google, call this `verification_url`.
+    #[serde(alias = "verification_url")]
+    pub verification_uri: String,
+    /// The verification page with the user code already filled in, if the provider supports it.
+    pub verification_uri_complete: Option<String>,
+    /// The lifetime of the codes in seconds.
+    pub expires_in: u64,
+    /// The minimum polling interval in seconds.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(error: &str) -> Result<TokenResponse, TokenError> {
+        Err(TokenError {
+            error: error.to_string(),
+            error_description: None,
+        })
+    }
+
+    #[test]
+    fn polling_waits_slows_down_and_completes() {
+        let mut polling = Polling {
+            interval: Duration::from_secs(5),
+        };
+
+        assert!(polling.handle(error("authorization_pending")).is_none());
+        assert_eq!(polling.interval, Duration::from_secs(5));
+        assert!(polling.handle(error("slow_down")).is_none());
+        assert_eq!(polling.interval, Duration::from_secs(10));
+        assert!(polling.handle(error("authorization_pending")).is_none());
+        assert_eq!(polling.interval, Duration::from_secs(10));
+
+        let tokens = serde_json::from_value(serde_json::json!({
+            "access_token": "device-access-token",
+            "token_type": "Bearer",
+        }))
+        .unwrap();
+        match polling.handle(Ok(tokens)) {
+            Some(Ok(tokens)) => assert_eq!(tokens.access_token.secret(), "device-access-token"),
+            _ => panic!("Expected the tokens"),
+        }
+    }
+
+    #[test]
+    fn polling_stops_on_final_errors() {
+        let mut polling = Polling {
+            interval: Duration::from_secs(5),
+        };
+
+        assert!(matches!(
+            polling.handle(error("access_denied")),
+            Some(Err(Error::Authorization { error, .. })) if error == "access_denied"
+        ));
+        assert!(matches!(
+            polling.handle(error("expired_token")),
+            Some(Err(Error::Timeout))
+        ));
+        assert!(matches!(
+            polling.handle(error("invalid_client")),
+            Some(Err(Error::TokenRequest { error, .. })) if error == "invalid_client"
+        ));
+    }
+}
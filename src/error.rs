@@ -0,0 +1,155 @@
+use serde::{ser::SerializeStruct, Serializer};
+
+/// The error type of this crate.
+///
+/// Serializes to `{ "kind": "...", "message": "..." }` so the frontend can match on the `kind` of errors
+/// returned by the plugin commands.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The server couldn't bind to the configured address.
+    #[error("Failed to bind the server: {0}")]
+    Bind(#[source] std::io::Error),
+    /// All of the configured ports are already in use.
+    #[error("None of the configured ports ({}) are available", join_ports(.0))]
+    NoFreePort(Vec<u16>),
+    /// The config (or a flow's endpoint) is invalid.
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    /// A request to the server couldn't be parsed or didn't contain the expected data.
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    /// A request to the server exceeded [`OauthConfig::max_request_size`](crate::OauthConfig::max_request_size).
+    #[error("The request exceeds the maximum size of {0} bytes")]
+    RequestTooLarge(usize),
+    /// A request was rejected because of its peer address or its `Host` or `Origin` header.
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    /// The `state` parameter of the callback didn't match the expected value.
+    #[error("The state parameter of the callback doesn't match")]
+    StateMismatch,
+    /// The operation didn't complete in time.
+    #[error("The operation timed out")]
+    Timeout,
+    /// The handler or the server thread panicked.
+    #[error("The handler panicked: {0}")]
+    HandlerPanicked(String),
+    /// The handler returned an error, see [`start_fallible`](crate::start_fallible) and
+    /// [`HandlerResult::Error`](crate::HandlerResult::Error).
+    #[error("The handler failed: {0}")]
+    HandlerFailed(String),
+    /// A callback arrived after the first one was handled, see
+    /// [`OauthConfig::allow_repeated_callbacks`](crate::OauthConfig::allow_repeated_callbacks).
+    #[error("The callback was already handled")]
+    CallbackReplayed,
+    /// No server started by the plugin is running on this port.
+    #[error("No server started by the plugin is running on port {0}")]
+    ServerNotFound(u16),
+    /// The server was stopped before it received a callback.
+    #[error("The server stopped before receiving a callback")]
+    Cancelled,
+    /// The received callback URL couldn't be parsed.
+    #[error("Invalid callback URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    /// The provider returned an error instead of authorizing the app, for example `access_denied`.
+    #[error("Authorization failed: {}", describe(.error, .description.as_deref()))]
+    Authorization {
+        /// The `error` parameter.
+        error: String,
+        /// The `error_description` parameter.
+        description: Option<String>,
+    },
+    /// The token endpoint rejected the request, for example with `invalid_grant`.
+    #[cfg(feature = "oauth2")]
+    #[error("Token request failed: {}", describe(.error, .description.as_deref()))]
+    TokenRequest {
+        /// The `error` field of the response, or the HTTP status if the response wasn't a standard error response.
+        error: String,
+        /// The `error_description` field of the response.
+        description: Option<String>,
+    },
+    /// The token set expired and can't be refreshed.
+    #[cfg(feature = "oauth2")]
+    #[error("The access token expired and there is no refresh token")]
+    NoRefreshToken,
+    /// An HTTP request to the provider failed.
+    #[cfg(feature = "oauth2")]
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The provider's response was not what the protocol prescribes.
+    #[cfg(feature = "oauth2")]
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    /// An `id_token` failed validation.
+    #[cfg(feature = "oidc")]
+    #[error("Invalid id_token: {0}")]
+    InvalidToken(String),
+    /// The OS keychain couldn't be accessed.
+    #[cfg(feature = "storage")]
+    #[error("Keychain error: {0}")]
+    Keyring(#[from] keyring::Error),
+    /// Any other I/O error.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    /// A stable, machine-readable name of the error variant, like `state_mismatch`.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Bind(_) => "bind",
+            Error::NoFreePort(_) => "no_free_port",
+            Error::InvalidConfig(_) => "invalid_config",
+            Error::InvalidRequest(_) => "invalid_request",
+            Error::RequestTooLarge(_) => "request_too_large",
+            Error::Forbidden(_) => "forbidden",
+            Error::StateMismatch => "state_mismatch",
+            Error::Timeout => "timeout",
+            Error::HandlerPanicked(_) => "handler_panicked",
+            Error::HandlerFailed(_) => "handler_failed",
+            Error::CallbackReplayed => "callback_replayed",
+            Error::ServerNotFound(_) => "server_not_found",
+            Error::Cancelled => "cancelled",
+            Error::InvalidUrl(_) => "invalid_url",
+            Error::Authorization { .. } => "authorization",
+            #[cfg(feature = "oauth2")]
+            Error::TokenRequest { .. } => "token_request",
+            #[cfg(feature = "oauth2")]
+            Error::NoRefreshToken => "no_refresh_token",
+            #[cfg(feature = "oauth2")]
+            Error::Http(_) => "http",
+            #[cfg(feature = "oauth2")]
+            Error::InvalidResponse(_) => "invalid_response",
+            #[cfg(feature = "oidc")]
+            Error::InvalidToken(_) => "invalid_token",
+            #[cfg(feature = "storage")]
+            Error::Keyring(_) => "keyring",
+            Error::Io(_) => "io",
+        }
+    }
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Error", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+pub(crate) fn describe(error: &str, description: Option<&str>) -> String {
+    match description {
+        Some(description) => format!("{} ({})", error, description),
+        None => error.to_string(),
+    }
+}
+
+fn join_ports(ports: &[u16]) -> String {
+    ports
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
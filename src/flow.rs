@@ -0,0 +1,418 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use url::Url;
+
+use crate::{
+    authorization::parse_endpoint, pkce::PkcePair, start_async, AuthorizationRequest, Error,
+    OauthConfig, PendingCallback,
+};
+
+/// The full authorization code flow with PKCE: Builds the authorization URL, runs the localhost server
+/// and exchanges the received code for tokens.
+///
+/// Call [`OauthFlow::start`], open [`PendingAuthorization::authorization_url`] in the user's browser
+/// and then await [`PendingAuthorization::finish`].
+#[derive(Debug, Clone)]
+pub struct OauthFlow {
+    client_id: String,
+    client_secret: Option<String>,
+    authorization_endpoint: Url,
+    token_endpoint: Url,
+    scopes: Vec<String>,
+    params: Vec<(String, String)>,
+    config: OauthConfig,
+    redirect_host: Option<String>,
+}
+
+impl OauthFlow {
+    /// Creates a new flow for a client registered with the provider.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if one of the endpoints is not a valid URL.
+    pub fn new(
+        client_id: impl Into<String>,
+        authorization_endpoint: &str,
+        token_endpoint: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self::from_endpoints(
+            client_id.into(),
+            parse_endpoint(authorization_endpoint)?,
+            parse_endpoint(token_endpoint)?,
+        ))
+    }
+
+    pub(crate) fn from_endpoints(
+        client_id: String,
+        authorization_endpoint: Url,
+        token_endpoint: Url,
+    ) -> Self {
+        Self {
+            client_id,
+            client_secret: None,
+            authorization_endpoint,
+            token_endpoint,
+            scopes: Vec::new(),
+            params: Vec::new(),
+            config: OauthConfig::default(),
+            redirect_host: None,
+        }
+    }
+
+    /// Sets the client secret. Only use this if your provider requires one even for PKCE clients,
+    /// because it can be extracted from the app's binary.
+    #[must_use]
+    pub fn client_secret(mut self, client_secret: impl Into<String>) -> Self {
+        self.client_secret = Some(client_secret.into());
+        self
+    }
+
+    /// Sets the scopes to request.
+    #[must_use]
+    pub fn scopes<I: IntoIterator<Item = S>, S: Into<String>>(mut self, scopes: I) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Adds an extra query parameter to the authorization URL, for example `prompt=consent`.
+    #[must_use]
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the config for the localhost server. The `state` value is always generated by the flow.
+    #[must_use]
+    pub fn config(mut self, config: OauthConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the host used in the `redirect_uri`, for example `localhost` for providers that don't accept IP addresses.
+    /// The server still binds to the configured address. Hosts other than `localhost` and loopback addresses
+    /// must be accepted by [`OauthConfig::host_validator`].
+    ///
+    /// Default: The address the server is bound to, or `127.0.0.1` if it's bound to an unspecified address.
+    #[must_use]
+    pub fn redirect_host(mut self, host: impl Into<String>) -> Self {
+        self.redirect_host = Some(host.into());
+        self
+    }
+
+    /// Starts the localhost server and builds the authorization URL for it.
+    ///
+    /// # Errors
+    ///
+    /// - Returns the errors of [`start_async`](crate::start_async) if the server creation fails.
+    /// - Returns [`Error::InvalidConfig`] if the `redirect_host` is not a valid host.
+    pub fn start(mut self) -> Result<PendingAuthorization, Error> {
+        let state = self.config.generate_state().to_string();
+        let callback = start_async(self.config)?;
+
+        let mut redirect_uri = callback.redirect_uri();
+        if let Some(host) = &self.redirect_host {
+            let mut uri = Url::parse(&redirect_uri)?;
+            uri.set_host(Some(host)).map_err(|err| {
+                Error::InvalidConfig(format!("Invalid redirect host {:?}: {}", host, err))
+            })?;
+            redirect_uri = uri.to_string();
+        }
+
+        let pkce = PkcePair::generate();
+        let mut request = AuthorizationRequest::from_endpoint(
+            self.authorization_endpoint,
+            self.client_id.clone(),
+        )
+        .redirect_uri(redirect_uri.clone())
+        .state(state.clone())
+        .scopes(self.scopes)
+        .pkce(&pkce);
+        for (name, value) in self.params {
+            request = request.param(name, value);
+        }
+        let authorization_url = request.build();
+
+        Ok(PendingAuthorization {
+            authorization_url,
+            redirect_uri,
+            callback,
+            state,
+            pkce,
+            client_id: self.client_id,
+            client_secret: self.client_secret,
+            token_endpoint: self.token_endpoint,
+        })
+    }
+}
+
+/// A started [`OauthFlow`] waiting for the user to log in.
+///
+/// Dropping it stops the localhost server.
+pub struct PendingAuthorization {
+    authorization_url: Url,
+    redirect_uri: String,
+    callback: PendingCallback,
+    state: String,
+    pkce: PkcePair,
+    client_id: String,
+    client_secret: Option<String>,
+    token_endpoint: Url,
+}
+
+impl PendingAuthorization {
+    /// The URL to open in the user's browser.
+    #[must_use]
+    pub fn authorization_url(&self) -> &Url {
+        &self.authorization_url
+    }
+
+    /// The `redirect_uri` pointing to the localhost server.
+    #[must_use]
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// The port the server is listening on.
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.callback.port()
+    }
+
+    /// Waits for the redirect and exchanges the received code for tokens.
+    ///
+    /// # Errors
+    ///
+    /// - Returns the errors of [`PendingCallback`] if the server stopped before receiving a callback.
+    /// - Returns [`Error::Authorization`] if the provider returned an error instead of a code.
+    /// - Returns [`Error::StateMismatch`] if the callback's `state` doesn't match.
+    /// - Returns [`Error::InvalidRequest`] if the callback didn't contain a code.
+    /// - Returns [`Error::TokenRequest`] if the provider rejected the token request
+    ///   and [`Error::Http`] or [`Error::InvalidResponse`] if the request itself failed.
+    pub async fn finish(self) -> Result<TokenResponse, Error> {
+        let response = self.callback.await?;
+        if let Some(error) = response.error {
+            return Err(Error::Authorization {
+                error,
+                description: response.error_description,
+            });
+        }
+        // The server already rejects mismatching callbacks, this only guards against a misconfigured server.
+        if response.state.as_deref() != Some(self.state.as_str()) {
+            return Err(Error::StateMismatch);
+        }
+        let code = response.code.ok_or_else(|| {
+            Error::InvalidRequest("The callback didn't contain a code".to_string())
+        })?;
+
+        OauthClient {
+            client_id: self.client_id,
+            client_secret: self.client_secret,
+            token_endpoint: self.token_endpoint,
+        }
+        .exchange_code(&code, &self.redirect_uri, Some(self.pkce.verifier()))
+        .await
+    }
+}
+
+/// A client registered with a provider, for token exchanges outside of an [`OauthFlow`].
+///
+/// Register it with [`Oauth::register_client`](crate::Oauth::register_client) to let the frontend
+/// exchange codes via the plugin's `exchange_code` command without ever seeing the client secret.
+#[derive(Debug, Clone)]
+pub struct OauthClient {
+    client_id: String,
+    client_secret: Option<String>,
+    token_endpoint: Url,
+}
+
+impl OauthClient {
+    /// Creates a client without a secret.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if `token_endpoint` is not a valid URL.
+    pub fn new(client_id: impl Into<String>, token_endpoint: &str) -> Result<Self, Error> {
+        Ok(Self {
+            client_id: client_id.into(),
+            client_secret: None,
+            token_endpoint: parse_endpoint(token_endpoint)?,
+        })
+    }
+
+    /// Creates a client using the token endpoint of a [`Provider`](crate::providers::Provider).
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if the provider's token endpoint is not a valid URL.
+    pub fn from_provider(
+        provider: &crate::providers::Provider,
+        client_id: impl Into<String>,
+    ) -> Result<Self, Error> {
+        Self::new(client_id, provider.token_endpoint)
+    }
+
+    /// Sets the client secret. Only use this if your provider requires one even for PKCE clients,
+    /// because it can be extracted from the app's binary.
+    #[must_use]
+    pub fn client_secret(mut self, client_secret: impl Into<String>) -> Self {
+        self.client_secret = Some(client_secret.into());
+        self
+    }
+
+    /// Exchanges an authorization code for tokens.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::TokenRequest`] if the provider rejected the request
+    ///   and [`Error::Http`] or [`Error::InvalidResponse`] if the request itself failed.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: Option<&str>,
+    ) -> Result<TokenResponse, Error> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", &self.client_id),
+        ];
+        if let Some(code_verifier) = code_verifier {
+            form.push(("code_verifier", code_verifier));
+        }
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        request_token(&self.token_endpoint, &form).await
+    }
+}
+
+/// The token set returned by the token endpoint.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TokenResponse {
+    /// The access token.
+    pub access_token: AccessToken,
+    /// The token type, usually `Bearer`.
+    pub token_type: String,
+    /// The lifetime of the access token in seconds, if the provider returned one.
+    pub expires_in: Option<u64>,
+    /// When the access token expires, as a unix timestamp in seconds. Set from `expires_in` when the tokens are received,
+    /// so it's still accurate for tokens loaded from a [`TokenStore`](crate::TokenStore) later.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// The refresh token, if the provider issued one.
+    pub refresh_token: Option<RefreshToken>,
+    /// The granted scopes if they differ from the requested ones.
+    pub scope: Option<String>,
+    /// The OpenID Connect `id_token`, if `openid` was requested.
+    pub id_token: Option<String>,
+}
+
+/// An access token. Its `Debug` output is redacted so it doesn't end up in logs by accident.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct AccessToken(String);
+
+impl AccessToken {
+    /// The raw token value.
+    #[must_use]
+    pub fn secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AccessToken([redacted])")
+    }
+}
+
+/// A refresh token. Its `Debug` output is redacted so it doesn't end up in logs by accident.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct RefreshToken(String);
+
+impl RefreshToken {
+    /// The raw token value.
+    #[must_use]
+    pub fn secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for RefreshToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RefreshToken([redacted])")
+    }
+}
+
+/// An error response of the token endpoint.
+#[derive(serde::Deserialize)]
+pub(crate) struct TokenError {
+    pub(crate) error: String,
+    pub(crate) error_description: Option<String>,
+}
+
+impl From<TokenError> for Error {
+    fn from(err: TokenError) -> Self {
+        Error::TokenRequest {
+            error: err.error,
+            description: err.error_description,
+        }
+    }
+}
+
+pub(crate) async fn request_token(
+    token_endpoint: &Url,
+    form: &[(&str, &str)],
+) -> Result<TokenResponse, Error> {
+    post_token_request(token_endpoint, form)
+        .await?
+        .map_err(Error::from)
+}
+
+/// Sends a request to the token endpoint. The provider's error response is returned as the inner `Err`.
+pub(crate) async fn post_token_request(
+    token_endpoint: &Url,
+    form: &[(&str, &str)],
+) -> Result<Result<TokenResponse, TokenError>, Error> {
+    let response = reqwest::Client::new()
+        .post(token_endpoint.clone())
+        .header(reqwest::header::ACCEPT, "application/json")
+        .form(form)
+        .send()
+        .await?;
+
+    let status = response.status();
+    let body = response.bytes().await?;
+
+    // Some providers, like GitHub, answer with a 200 status code even for errors.
+    if let Ok(err) = serde_json::from_slice::<TokenError>(&body) {
+        return Ok(Err(err));
+    }
+    if !status.is_success() {
+        return Err(Error::TokenRequest {
+            error: status.to_string(),
+            description: None,
+        });
+    }
+
+    let mut tokens: TokenResponse =
+        serde_json::from_slice(&body).map_err(|err| Error::InvalidResponse(err.to_string()))?;
+    if let Some(expires_in) = tokens.expires_in {
+        tokens.expires_at = Some(expiry_timestamp(expires_in));
+    }
+    Ok(Ok(tokens))
+}
+
+/// The unix timestamp in seconds of `expires_in` seconds from now.
+pub(crate) fn expiry_timestamp(expires_in: u64) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs().saturating_add(expires_in)
+}
@@ -0,0 +1,327 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    time::Instant,
+};
+
+use crate::Error;
+
+/// A request read from a connection, independent of the connection's transport.
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    /// The first header with this name, compared case-insensitively.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response to be written to a connection. `Content-Length` is added when it's written, except for 204 responses.
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+    /// Whether only the headers are written, for `HEAD` requests. `Content-Length` still describes the body.
+    pub(crate) head_only: bool,
+}
+
+impl Response {
+    pub(crate) fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.into(),
+            head_only: false,
+        }
+    }
+
+    pub(crate) fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub(crate) fn without_body(mut self) -> Self {
+        self.head_only = true;
+        self
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, conn: &mut W) -> Result<(), std::io::Error> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        // A 204 response can't have a body, so it must not have a `Content-Length` either (RFC 9110, section 8.6).
+        if self.status != 204 {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+
+        conn.write_all(head.as_bytes())?;
+        if !self.head_only {
+            conn.write_all(&self.body)?;
+        }
+        conn.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        302 => "Found",
+        303 => "See Other",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        410 => "Gone",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Reads a request including its body, which may be sent with `Content-Length` or chunked.
+/// Returns `None` if the client closed the connection or timed out before sending anything.
+///
+/// The whole request has to arrive before `deadline`, so a client sending one byte at a time
+/// can't keep the connection busy until `max_size` is reached.
+pub(crate) fn read_request<C: Read>(
+    conn: &mut C,
+    max_size: usize,
+    deadline: Instant,
+) -> Result<Option<Request>, Error> {
+    let mut conn = Deadline {
+        inner: conn,
+        deadline,
+    };
+    let (buffer, header_len) = match read_head(&mut conn, max_size)? {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut parsed = httparse::Request::new(&mut headers);
+    parsed
+        .parse(&buffer)
+        .map_err(|err| Error::InvalidRequest(err.to_string()))?;
+    let mut request = Request {
+        method: parsed.method.unwrap_or_default().to_string(),
+        path: parsed.path.unwrap_or_default().to_string(),
+        headers: parsed
+            .headers
+            .iter()
+            .map(|header| {
+                (
+                    header.name.to_string(),
+                    String::from_utf8_lossy(header.value).to_string(),
+                )
+            })
+            .collect(),
+        body: Vec::new(),
+    };
+
+    // The rest of the request is read through a buffer that starts with what was read past the headers.
+    let remaining = max_size.saturating_sub(header_len);
+    let mut reader = BufReader::new((&buffer[header_len..]).chain(conn));
+    let chunked = request
+        .header("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+    if chunked {
+        request.body = read_chunked(&mut reader, remaining)?;
+    } else if let Some(length) = request.header("Content-Length") {
+        let length = length
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| Error::InvalidRequest(format!("Invalid Content-Length {:?}", length)))?;
+        if length > remaining {
+            return Err(Error::RequestTooLarge(max_size));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        request.body = body;
+    }
+
+    Ok(Some(request))
+}
+
+/// Fails reads once the deadline passed, see [`read_request`].
+struct Deadline<R> {
+    inner: R,
+    deadline: Instant,
+}
+
+impl<R: Read> Read for Deadline<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if Instant::now() >= self.deadline {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "The request wasn't complete in time",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+fn read_head<C: Read>(conn: &mut C, max_size: usize) -> Result<Option<(Vec<u8>, usize)>, Error> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let read = match conn.read(&mut chunk) {
+            Ok(read) => read,
+            // Idle connections, like unused browser preconnects, are dropped silently once they timed out.
+            Err(err)
+                if buffer.is_empty()
+                    && matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+            {
+                return Ok(None)
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if read == 0 {
+            if buffer.is_empty() {
+                return Ok(None);
+            }
+            return Err(Error::InvalidRequest(
+                "The connection was closed before the request headers were complete".to_string(),
+            ));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let status = httparse::Request::new(&mut headers)
+            .parse(&buffer)
+            .map_err(|err| Error::InvalidRequest(err.to_string()))?;
+        match status {
+            httparse::Status::Complete(header_len) => return Ok(Some((buffer, header_len))),
+            httparse::Status::Partial if buffer.len() > max_size => {
+                return Err(Error::RequestTooLarge(max_size))
+            }
+            httparse::Status::Partial => {}
+        }
+    }
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R, max_size: usize) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader, max_size)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| Error::InvalidRequest(format!("Invalid chunk size {:?}", size)))?;
+        if size == 0 {
+            break;
+        }
+        if body.len() + size > max_size {
+            return Err(Error::RequestTooLarge(max_size));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        if !read_line(reader, max_size)?.is_empty() {
+            return Err(Error::InvalidRequest(
+                "Chunk is longer than its size".to_string(),
+            ));
+        }
+    }
+    // Trailers are ignored.
+    while !read_line(reader, max_size)?.is_empty() {}
+
+    Ok(body)
+}
+
+/// Reads a CRLF terminated line without the line break.
+fn read_line<R: BufRead>(reader: &mut R, max_size: usize) -> Result<String, Error> {
+    let mut line = Vec::new();
+    reader
+        .take(max_size as u64 + 2)
+        .read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\r\n") {
+        return Err(Error::InvalidRequest(
+            "The connection was closed before the request body was complete".to_string(),
+        ));
+    }
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    /// Sends an endless request header one byte at a time.
+    struct Trickle;
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            thread::sleep(Duration::from_millis(10));
+            buf[0] = b'a';
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn slow_request_is_dropped_at_the_deadline() {
+        let started = Instant::now();
+        let result = read_request(
+            &mut Trickle,
+            64 * 1024,
+            started + Duration::from_millis(100),
+        );
+        assert!(
+            matches!(&result, Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::TimedOut),
+            "{:?}",
+            result.map(|_| ())
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn complete_request_is_read() {
+        let mut conn: &[u8] = b"POST /cb HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+        let request = read_request(&mut conn, 1024, Instant::now() + Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/cb");
+        assert_eq!(request.body, b"abc");
+    }
+
+    #[test]
+    fn no_content_response_has_no_content_length() {
+        let mut written = Vec::new();
+        Response::new(204, "text/html", "")
+            .write_to(&mut written)
+            .unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(
+            written.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "{}",
+            written
+        );
+        assert!(!written.contains("Content-Length"), "{}", written);
+
+        let mut written = Vec::new();
+        Response::new(200, "text/html", "ok")
+            .write_to(&mut written)
+            .unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("\r\nContent-Length: 2\r\n"), "{}", written);
+    }
+}
@@ -1,16 +1,73 @@
 use std::{
-    borrow::Cow,
-    io::{Read, Write},
-    net::{SocketAddr, TcpListener, TcpStream},
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::mpsc,
+    task::{Context, Poll},
     thread,
 };
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{rngs::OsRng, RngCore};
 use tauri::{
-    plugin::{Builder, TauriPlugin},
-    Runtime,
+    async_runtime::{channel, Receiver},
+    AppHandle, Manager, Runtime, Window,
 };
 
-const EXIT: [u8; 4] = [1, 3, 3, 7];
+#[cfg(feature = "tokio")]
+use crate::server::DEFAULT_HANDLER_TIMEOUT;
+use crate::server::{spawn_server, DEFAULT_ERROR_PAGE};
+
+#[macro_use]
+mod macros;
+
+mod authorization;
+mod config;
+#[cfg(feature = "oauth2")]
+pub mod device_flow;
+mod error;
+#[cfg(feature = "oauth2")]
+mod flow;
+mod http;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+pub mod pkce;
+mod plugin;
+#[cfg(feature = "oauth2")]
+pub mod providers;
+mod response;
+mod server;
+mod session;
+#[cfg(feature = "storage")]
+mod storage;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "oauth2")]
+mod token_manager;
+mod webview;
+
+pub use authorization::AuthorizationRequest;
+pub use config::{
+    CaptureMode, ErrorHandler, HostValidator, OauthConfig, RejectReason, Resource, ResponseMode,
+    TimeoutHandler, Validator,
+};
+pub use error::Error;
+#[cfg(feature = "oauth2")]
+pub use flow::{
+    AccessToken, OauthClient, OauthFlow, PendingAuthorization, RefreshToken, TokenResponse,
+};
+pub use plugin::{init, Oauth, OauthExt};
+pub use response::OauthResponse;
+pub use server::{cancel, HandlerResult, RequestInfo, ServerHandle, ServerStatus};
+pub use session::{AuthSession, Transport};
+#[cfg(feature = "storage")]
+pub use storage::TokenStore;
+#[cfg(feature = "oauth2")]
+pub use token_manager::{LoggedOut, SessionExpired, TokenEvent, TokenManager, TokenRefreshed};
+pub use webview::WebviewFlow;
 
 /// Starts the localhost (using 127.0.0.1) server. Returns the port its listening on.
 ///
@@ -22,31 +79,21 @@ const EXIT: [u8; 4] = [1, 3, 3, 7];
 ///
 /// # Errors
 ///
-/// - Returns `std::io::Error` if the server creation fails.
-///
-/// # Panics
+/// - Returns [`Error::Bind`] if the server creation fails.
 ///
-/// The seperate server thread can panic if its unable to send the html response to the client. This may change after more real world testing.
-pub fn start<F: FnMut(String) + Send + 'static>(handler: F) -> Result<u16, std::io::Error> {
+/// Errors that happen while the server is running are logged, see [`OauthConfig::on_error`] to handle them yourself.
+pub fn start<F: FnMut(String) + Send + 'static>(handler: F) -> Result<u16, Error> {
     start_with_config(OauthConfig::default(), handler)
 }
 
-/// The optional server config.
-#[derive(Default, serde::Deserialize)]
-pub struct OauthConfig {
-    /// An array of hard-coded ports the server should try to bind to.
-    /// This should only be used if your oauth provider does not accept wildcard localhost addresses.
-    ///
-    /// Default: Asks the system for a free port.
-    pub ports: Option<Vec<u16>>,
-    /// Optional static html string send to the user after being redirected.
-    /// Keep it self-contained and as small as possible.
-    ///
-    /// Default: `"<html><body>Please return to the app.</body></html>"`.
-    pub response: Option<Cow<'static, str>>,
+/// Generates 128 random bits encoded as URL-safe base64.
+fn random_token() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
 }
 
-/// Starts the localhost (using 127.0.0.1) server. Returns the port its listening on.
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns the port its listening on.
 ///
 /// Because of the unprotected localhost port, you _must_ verify the URL in the handler function.
 ///
@@ -57,192 +104,467 @@ pub struct OauthConfig {
 ///
 /// # Errors
 ///
-/// - Returns `std::io::Error` if the server creation fails.
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_with_config<F: FnMut(String) + Send + 'static>(
+    config: OauthConfig,
+    mut handler: F,
+) -> Result<u16, Error> {
+    spawn_server(config, move |url, _| {
+        handler(url);
+        HandlerResult::Default
+    })
+    .map(ServerHandle::detach)
+}
+
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns the port its listening on.
+///
+/// Same as [`start_with_config`] but the handler receives the parsed [`OauthResponse`] instead of the raw URL.
+/// If the received URL can't be parsed the handler is not executed and the server exits.
 ///
-/// # Panics
+/// Because of the unprotected localhost port, you _must_ verify the response in the handler function.
 ///
-/// The seperate server thread can panic if its unable to send the html response to the client. This may change after more real world testing.
-pub fn start_with_config<F: FnMut(String) + Send + 'static>(
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+/// * `handler` - Closure which will be executed on a successful connection. It receives the parsed [`OauthResponse`].
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_parsed<F: FnMut(OauthResponse) + Send + 'static>(
     config: OauthConfig,
     mut handler: F,
-) -> Result<u16, std::io::Error> {
-    let listener = match config.ports {
-        Some(ports) => TcpListener::bind(
-            ports
-                .iter()
-                .map(|p| SocketAddr::from(([127, 0, 0, 1], *p)))
-                .collect::<Vec<SocketAddr>>()
-                .as_slice(),
-        ),
-        None => TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))),
-    }?;
-
-    let port = listener.local_addr()?.port();
-
-    thread::spawn(move || {
-        for conn in listener.incoming() {
-            match conn {
-                Ok(conn) => {
-                    if let Some(url) = handle_connection(conn, config.response.as_deref(), port) {
-                        // Using an empty string to communicate that a shutdown was requested.
-                        if !url.is_empty() {
-                            handler(url);
-                        }
-                        // TODO: Check if exiting here is always okay.
-                        break;
-                    }
-                }
-                Err(err) => {
-                    log::error!("Error reading incoming connection: {}", err.to_string());
-                }
-            }
+) -> Result<u16, Error> {
+    start_with_config(config, move |url| match OauthResponse::parse(&url) {
+        Ok(response) => handler(response),
+        Err(err) => error!("Received invalid callback URL: {}", err),
+    })
+}
+
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns the port its listening on.
+///
+/// Same as [`start_parsed`] but the handler is executed at most once and the server exits afterwards,
+/// even if [`OauthConfig::persistent`] is set.
+///
+/// Because of the unprotected localhost port, you _must_ verify the response in the handler function.
+///
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+/// * `handler` - Closure which will be executed on the first successful connection. It receives the parsed [`OauthResponse`].
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_once<F: FnOnce(OauthResponse) + Send + 'static>(
+    mut config: OauthConfig,
+    handler: F,
+) -> Result<u16, Error> {
+    config.persistent = None;
+    let mut handler = Some(handler);
+    start_parsed(config, move |response| {
+        if let Some(handler) = handler.take() {
+            handler(response);
         }
-    });
+    })
+}
 
-    Ok(port)
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns the port its listening on
+/// and a [`Receiver`](std::sync::mpsc::Receiver) for the parsed responses, as an alternative to passing a handler closure.
+///
+/// The server exits after the first callback unless [`OauthConfig::persistent`] is set, use [`cancel`] to stop
+/// a persistent server. Callback URLs that can't be parsed are skipped.
+///
+/// Because of the unprotected localhost port, you _must_ verify the received responses.
+///
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_with_channel(
+    config: OauthConfig,
+) -> Result<(u16, mpsc::Receiver<OauthResponse>), Error> {
+    let (sender, receiver) = mpsc::channel();
+    let port = start_parsed(config, move |response| {
+        // Sending only fails if the receiver was dropped, so nobody is waiting for the response anymore.
+        let _ = sender.send(response);
+    })?;
+    Ok((port, receiver))
 }
 
-fn handle_connection(mut conn: TcpStream, response: Option<&str>, port: u16) -> Option<String> {
-    let mut buffer = [0; 4048];
-    if let Err(io_err) = conn.read(&mut buffer) {
-        log::error!("Error reading incoming connection: {}", io_err.to_string());
-    };
-    if buffer[..4] == EXIT {
-        return Some(String::new());
-    }
+/// Same as [`start_with_channel`] but returns an async [`Receiver`] for use in async code.
+///
+/// Up to 16 responses are buffered. If the receiver doesn't keep up, the server waits before answering further callbacks.
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_with_async_channel(
+    config: OauthConfig,
+) -> Result<(u16, Receiver<OauthResponse>), Error> {
+    let (sender, receiver) = channel(16);
+    // The handler runs on the server's thread, outside of the async runtime, so blocking is fine here.
+    let port = start_parsed(config, move |response| {
+        let _ = sender.blocking_send(response);
+    })?;
+    Ok((port, receiver))
+}
 
-    let mut headers = [httparse::EMPTY_HEADER; 32];
-    let mut request = httparse::Request::new(&mut headers);
-    request.parse(&buffer).ok()?;
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns the port its listening on.
+///
+/// Instead of executing a handler, the server emits the received URL to the window via the `oauth://url` event,
+/// or the error via `oauth://invalid-url` if the URL couldn't be parsed, like the `start()` command of the plugin.
+/// This allows handling the redirect in the frontend without writing a custom command.
+///
+/// Because of the unprotected localhost port, you _must_ verify the URL in the frontend.
+///
+/// # Arguments
+///
+/// * `window` - The window receiving the events.
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_with_window<R: Runtime>(window: Window<R>, config: OauthConfig) -> Result<u16, Error> {
+    start_with_config(
+        config,
+        event_emitter(move |event, payload| window.emit(event, payload)),
+    )
+}
 
-    let path = request.path.unwrap_or_default();
+/// Same as [`start_with_window`] but the events are emitted to all windows of the app.
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_with_app<R: Runtime>(app: AppHandle<R>, config: OauthConfig) -> Result<u16, Error> {
+    start_with_config(
+        config,
+        event_emitter(move |event, payload| app.emit_all(event, payload)),
+    )
+}
 
-    if path == "/exit" {
-        return Some(String::new());
-    };
+/// Creates a handler emitting the `oauth://url` and `oauth://invalid-url` events via `emit`.
+fn event_emitter<E: Fn(&str, String) -> tauri::Result<()> + Send + 'static>(
+    emit: E,
+) -> impl FnMut(String) + Send + 'static {
+    move |url| {
+        let (event, payload) = match url::Url::parse(&url) {
+            Ok(_) => ("oauth://url", url),
+            Err(err) => ("oauth://invalid-url", err.to_string()),
+        };
+        if let Err(emit_err) = emit(event, payload) {
+            error!("Error emitting {} event: {}", event, emit_err)
+        };
+    }
+}
 
-    let mut is_localhost = false;
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns a [`ServerHandle`] to manage the server's lifecycle.
+///
+/// Unlike [`start_with_config`], the server is stopped as soon as the returned handle is dropped,
+/// so make sure to keep it around, for example in Tauri's managed state, until the flow completed.
+///
+/// Because of the unprotected localhost port, you _must_ verify the URL in the handler function.
+///
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+/// * `handler` - Closure which will be executed on a successful connection. It receives the full URL as a String.
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_with_handle<F: FnMut(String) + Send + 'static>(
+    config: OauthConfig,
+    mut handler: F,
+) -> Result<ServerHandle, Error> {
+    spawn_server(config, move |url, _| {
+        handler(url);
+        HandlerResult::Default
+    })
+}
 
-    for header in &headers {
-        if header.name == "Full-Url" {
-            return Some(String::from_utf8_lossy(header.value).to_string());
-        } else if header.name == "Host" {
-            is_localhost = String::from_utf8_lossy(header.value).starts_with("localhost");
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns a [`ServerHandle`] to manage the server's lifecycle.
+///
+/// Same as [`start_with_handle`] but the handler receives the parsed [`OauthResponse`] and decides what the browser gets to see,
+/// for example a 400 error page if your app-side validation failed. The response is sent after the handler returned.
+/// If the received URL can't be parsed the handler is not executed and the browser gets a 400 error page.
+///
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+/// * `handler` - Closure which will be executed on a successful connection. It receives the parsed [`OauthResponse`].
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_with_response<F: FnMut(OauthResponse) -> HandlerResult + Send + 'static>(
+    config: OauthConfig,
+    mut handler: F,
+) -> Result<ServerHandle, Error> {
+    spawn_server(config, move |url, _| match OauthResponse::parse(&url) {
+        Ok(response) => handler(response),
+        Err(err) => {
+            error!("Received invalid callback URL: {}", err);
+            HandlerResult::Page {
+                status: 400,
+                body: DEFAULT_ERROR_PAGE.replace("{{error}}", "invalid_request"),
+            }
         }
-    }
-    if path == "/cb" {
-        log::error!(
-            "Client fetched callback path but the request didn't contain the expected header."
-        );
-    }
+    })
+}
 
-    let script = format!(
-        r#"<script>fetch("http://{}:{}/cb",{{headers:{{"Full-Url":window.location.href}}}})</script>"#,
-        if is_localhost {
-            "localhost"
-        } else {
-            "127.0.0.1"
-        },
-        port
-    );
-    let response = match response {
-        Some(s) if s.contains("<head>") => s.replace("<head>", &format!("<head>{}", script)),
-        Some(s) if s.contains("<body>") => {
-            s.replace("<body>", &format!("<head>{}</head><body>", script))
-        }
-        Some(s) => {
-            log::warn!(
-                "`response` does not contain a body or head element. Prepending a head element..."
-            );
-            format!("<head>{}</head>{}", script, s)
-        }
-        None => format!(
-            "<html><head>{}</head><body>Please return to the app.</body></html>",
-            script
-        ),
-    };
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns a [`ServerHandle`] to manage the server's lifecycle.
+///
+/// Same as [`start_with_response`] but the handler only reports whether processing the callback worked, for example the token exchange.
+/// On `Ok` the browser gets the configured response, on `Err` the [`OauthConfig::error_response`] page
+/// with the `server_error` error code, see [`HandlerResult::Error`].
+///
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+/// * `handler` - Closure which will be executed on a successful connection. It receives the parsed [`OauthResponse`].
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_fallible<
+    E: fmt::Display,
+    F: FnMut(OauthResponse) -> Result<(), E> + Send + 'static,
+>(
+    config: OauthConfig,
+    mut handler: F,
+) -> Result<ServerHandle, Error> {
+    start_with_response(config, move |response| match handler(response) {
+        Ok(()) => HandlerResult::Default,
+        Err(err) => HandlerResult::Error(err.to_string()),
+    })
+}
 
-    // TODO: Test if unwrapping here is safe (enough).
-    conn.write_all(
-        format!(
-            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
-            response.len(),
-            response
-        )
-        .as_bytes(),
-    )
-    .unwrap();
-    conn.flush().unwrap();
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns a [`ServerHandle`] to manage the server's lifecycle.
+///
+/// Same as [`start_with_response`] but the handler returns a future, so it can do async work like the token exchange
+/// and show the success or error page depending on its outcome, all while the browser waits for the response.
+/// The future is awaited on Tauri's async runtime. If it doesn't finish within [`OauthConfig::handler_timeout`],
+/// it's dropped and the browser gets the [`OauthConfig::error_response`] page, like for [`HandlerResult::Error`].
+///
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+/// * `handler` - Closure which will be executed on a successful connection. It receives the parsed [`OauthResponse`].
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+#[cfg(feature = "tokio")]
+pub fn start_with_async_response<F, Fut>(
+    config: OauthConfig,
+    mut handler: F,
+) -> Result<ServerHandle, Error>
+where
+    F: FnMut(OauthResponse) -> Fut + Send + 'static,
+    Fut: Future<Output = HandlerResult>,
+{
+    let timeout = config.handler_timeout.unwrap_or(DEFAULT_HANDLER_TIMEOUT);
+    start_with_response(config, move |response| {
+        let handled = handler(response);
+        // The handler runs on the server's thread, outside of the async runtime, so blocking is fine here.
+        tauri::async_runtime::block_on(async move {
+            tokio::time::timeout(timeout, handled)
+                .await
+                .unwrap_or_else(|_| {
+                    HandlerResult::Error(format!("The handler didn't finish within {:?}", timeout))
+                })
+        })
+    })
+}
 
-    None
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server. Returns a [`ServerHandle`] to manage the server's lifecycle.
+///
+/// Same as [`start_with_handle`] but the handler also receives the [`RequestInfo`] of the request that delivered the callback,
+/// for example to check the `User-Agent` or to log where it came from.
+///
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
+/// * `handler` - Closure which will be executed on a successful connection. It receives the full URL as a String and the [`RequestInfo`].
+///
+/// # Errors
+///
+/// - Returns [`Error::Bind`] if the server creation fails or [`Error::NoFreePort`] if none of the configured `ports` are free.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_with_request_info<F: FnMut(String, RequestInfo) + Send + 'static>(
+    config: OauthConfig,
+    mut handler: F,
+) -> Result<ServerHandle, Error> {
+    spawn_server(config, move |url, info| {
+        handler(url, info);
+        HandlerResult::Default
+    })
 }
 
-/// Stops the currently running server behind the provided port without executing the handler.
-/// Alternatively you can send a request to http://127.0.0.1:port/exit
+/// Starts the localhost (using 127.0.0.1 unless configured otherwise) server and returns a [`PendingCallback`]
+/// which resolves to the parsed [`OauthResponse`] once the redirect arrived. This is meant to be `.await`ed in async code,
+/// for example in an async Tauri command, instead of passing a handler closure.
+///
+/// The server is stopped if the returned future is dropped before it resolved.
+///
+/// Because of the unprotected localhost port, you _must_ verify the response after the future resolved.
+///
+/// # Arguments
+///
+/// * `config` - Configuration the server should use, see [`OauthConfig`].
 ///
 /// # Errors
 ///
-/// - Returns `std::io::Error` if the server couldn't be reached.
-pub fn cancel(port: u16) -> Result<(), std::io::Error> {
-    // Using tcp instead of something global-ish like an AtomicBool,
-    // so we don't have to dive into the set_nonblocking madness.
-    let mut stream = TcpStream::connect(SocketAddr::from(([127, 0, 0, 1], port)))?;
-    stream.write_all(&EXIT)?;
-    stream.flush()?;
+/// - Returns [`Error::Bind`] if the server creation fails.
+/// - Returns [`Error::InvalidConfig`] if the `bridge` URL or one of the `headers` is invalid.
+pub fn start_async(mut config: OauthConfig) -> Result<PendingCallback, Error> {
+    config.persistent = None;
+    let (sender, receiver) = channel(1);
+    let handle = start_with_handle(config, move |url| {
+        // The server exits after the first callback so the channel can't be full.
+        let _ = sender.try_send(url);
+    })?;
 
-    Ok(())
+    Ok(PendingCallback { handle, receiver })
 }
 
-mod plugin_impl {
-    use tauri::{Manager, Runtime, Window};
-
-    #[tauri::command]
-    pub(crate) fn start<R: Runtime>(
-        window: Window<R>,
-        config: Option<super::OauthConfig>,
-    ) -> Result<u16, String> {
-        let mut config = config.unwrap_or_default();
-        if config.response.is_none() {
-            config.response = window
-                .config()
-                .plugins
-                .0
-                .get("oauth")
-                .map(|v| v.as_str().unwrap().to_string().into());
+/// Starts the localhost server like [`start_async`] and opens the authorization URL in the default browser.
+///
+/// `{{redirect_uri}}` in `authorization_url` is replaced with the URL-encoded redirect URI of the server
+/// and `{{port}}` with its port, so the URL can only be built once the server is listening,
+/// for example `https://example.com/authorize?client_id=app&redirect_uri={{redirect_uri}}`.
+///
+/// Because of the unprotected localhost port, you _must_ verify the response after the future resolved.
+///
+/// The URL is opened with the OS's opener (`open`, `xdg-open` or `rundll32`), not via Tauri's shell API,
+/// so the `tauri > allowlist > shell > open` scope doesn't apply. If it should, call [`start_async`] and open
+/// the URL with `tauri::api::shell::open` yourself.
+///
+/// # Errors
+///
+/// - Returns the errors of [`start_async`].
+/// - Returns [`Error::InvalidConfig`] if the authorization URL is not a valid `http` or `https` URL.
+/// - Returns [`Error::Io`] if the browser couldn't be opened.
+pub fn start_and_open(
+    config: OauthConfig,
+    authorization_url: &str,
+) -> Result<PendingCallback, Error> {
+    let callback = start_async(config)?;
+
+    let redirect_uri: String =
+        url::form_urlencoded::byte_serialize(callback.redirect_uri().as_bytes()).collect();
+    let authorization_url = authorization_url
+        .replace("{{redirect_uri}}", &redirect_uri)
+        .replace("{{port}}", &callback.port().to_string());
+    // Only web URLs, the opener would launch anything else with its registered app.
+    match url::Url::parse(&authorization_url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+            open_browser(url.as_str())?;
+        }
+        _ => {
+            return Err(Error::InvalidConfig(format!(
+                "Invalid authorization URL {:?}",
+                authorization_url
+            )))
         }
+    }
 
-        crate::start_with_config(config, move |url| match url::Url::parse(&url) {
-            Ok(_) => {
-                if let Err(emit_err) = window.emit("oauth://url", url) {
-                    log::error!("Error emitting oauth://url event: {}", emit_err)
-                };
-            }
-            Err(err) => {
-                if let Err(emit_err) = window.emit("oauth://invalid-url", err.to_string()) {
-                    log::error!("Error emitting oauth://invalid-url event: {}", emit_err)
-                };
-            }
-        })
-        .map_err(|err| err.to_string())
+    Ok(callback)
+}
+
+/// Opens the URL with the default browser of the OS.
+///
+/// Not using `tauri::api::shell::open`: It needs the app's `ShellScope`, but [`start_and_open`] works without an `AppHandle`,
+/// and the `shell-open-api` feature of tauri it requires would be enabled for every app using this plugin.
+/// Only `http` and `https` URLs are passed here, which is also all the shell scope allows by default.
+fn open_browser(url: &str) -> Result<(), std::io::Error> {
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        // Unlike `cmd /C start`, this doesn't interpret characters like `&` in the URL.
+        let mut command = std::process::Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    };
+    #[cfg(target_os = "macos")]
+    let mut command = std::process::Command::new("open");
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let mut command = std::process::Command::new("xdg-open");
+
+    let mut child = command.arg(url).spawn()?;
+    // Reaping the process in the background, the browser may keep running after the opener exited.
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Future returned by [`start_async`], resolving to the parsed [`OauthResponse`] of the redirect.
+///
+/// Resolves to [`Error::Cancelled`] if the server was stopped before receiving a callback,
+/// to [`Error::Timeout`] if it stopped because of [`OauthConfig::auto_cancel_after`],
+/// or to [`Error::InvalidUrl`] if the received URL couldn't be parsed.
+pub struct PendingCallback {
+    handle: ServerHandle,
+    receiver: Receiver<String>,
+}
+
+impl PendingCallback {
+    /// The port the server is listening on. Use this to build the redirect URL before awaiting the callback.
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.handle.port()
     }
 
-    #[tauri::command]
-    pub(crate) fn cancel(port: u16) -> Result<(), String> {
-        crate::cancel(port).map_err(|err| err.to_string())
+    /// The address the server is listening on.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.handle.local_addr()
+    }
+
+    /// The redirect URI pointing to the server, see [`ServerHandle::redirect_uri`].
+    #[must_use]
+    pub fn redirect_uri(&self) -> String {
+        self.handle.redirect_uri()
+    }
+
+    /// Stops the server without waiting for a callback. The future will resolve to an error afterwards.
+    ///
+    /// # Errors
+    ///
+    /// This currently never fails.
+    pub fn stop(&self) -> Result<(), Error> {
+        self.handle.stop()
     }
 }
 
-/// Initializes the tauri plugin.
-/// Only use this if you need the JavaScript APIs.
-///
-/// Note for the `start()` command: If `response` is not provided it will fall back to the config
-/// in tauri.conf.json if set and will fall back to the library's default, see [`OauthConfig`].
-#[must_use]
-pub fn init<R: Runtime>() -> TauriPlugin<R> {
-    Builder::new("oauth")
-        .invoke_handler(tauri::generate_handler![
-            plugin_impl::start,
-            plugin_impl::cancel
-        ])
-        .build()
+impl Future for PendingCallback {
+    type Output = Result<OauthResponse, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_recv(cx).map(|url| {
+            let url = url.ok_or_else(|| {
+                if self.handle.timed_out() {
+                    Error::Timeout
+                } else {
+                    Error::Cancelled
+                }
+            })?;
+            Ok(OauthResponse::parse(&url)?)
+        })
+    }
 }
@@ -0,0 +1,28 @@
+// Log macros forwarding to `tracing` if the `tracing` feature is enabled and to `log` otherwise.
+
+macro_rules! error {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::error!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        log::error!($($arg)+);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        log::warn!($($arg)+);
+    }};
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        log::debug!($($arg)+);
+    }};
+}
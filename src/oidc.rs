@@ -0,0 +1,307 @@
+//! OpenID Connect discovery and `id_token` validation.
+
+use std::collections::HashMap;
+
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+
+use crate::{random_token, Error, OauthFlow};
+
+/// The subset of the provider's `/.well-known/openid-configuration` needed for a login.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ProviderMetadata {
+    /// The issuer identifier, compared to the `iss` claim of `id_token`s.
+    pub issuer: String,
+    /// The authorization endpoint.
+    pub authorization_endpoint: String,
+    /// The token endpoint.
+    pub token_endpoint: String,
+    /// The URL of the provider's JSON Web Key Set used to sign `id_token`s.
+    pub jwks_uri: String,
+    /// The userinfo endpoint, if the provider has one.
+    pub userinfo_endpoint: Option<String>,
+    /// The scopes the provider supports.
+    #[serde(default)]
+    pub scopes_supported: Vec<String>,
+}
+
+impl ProviderMetadata {
+    /// Fetches `<issuer>/.well-known/openid-configuration` and verifies that it belongs to `issuer`.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::Http`] if the request fails or the document is invalid.
+    /// - Returns [`Error::InvalidResponse`] if the document's `issuer` doesn't match `issuer`.
+    pub async fn discover(issuer: &str) -> Result<Self, Error> {
+        let issuer = issuer.trim_end_matches('/');
+        let metadata: Self =
+            get_json(&format!("{}/.well-known/openid-configuration", issuer)).await?;
+
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(Error::InvalidResponse(format!(
+                "Discovered issuer {:?} doesn't match {:?}",
+                metadata.issuer, issuer
+            )));
+        }
+
+        Ok(metadata)
+    }
+
+    /// Fetches the provider's signing keys from `jwks_uri`.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::Http`] if the request fails or the key set is invalid.
+    pub async fn fetch_jwks(&self) -> Result<Jwks, Error> {
+        get_json(&self.jwks_uri).await
+    }
+
+    /// Creates an [`OauthFlow`] for the discovered endpoints requesting the `openid` scope.
+    /// Add more scopes and a nonce (see [`generate_nonce`]) before starting it.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if one of the endpoints is not a valid URL.
+    pub fn flow(&self, client_id: impl Into<String>) -> Result<OauthFlow, Error> {
+        Ok(OauthFlow::new(
+            client_id,
+            &self.authorization_endpoint,
+            &self.token_endpoint,
+        )?
+        .scopes(["openid"]))
+    }
+
+    /// Fetches the provider's signing keys and validates `id_token`, see [`validate_id_token`].
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::Http`] if the keys couldn't be fetched.
+    /// - Returns [`Error::InvalidToken`] if the token is invalid.
+    pub async fn validate_id_token(
+        &self,
+        id_token: &str,
+        client_id: &str,
+        nonce: Option<&str>,
+    ) -> Result<IdTokenClaims, Error> {
+        let jwks = self.fetch_jwks().await?;
+        validate_id_token(id_token, &jwks, &self.issuer, client_id, nonce)
+    }
+}
+
+/// The provider's JSON Web Key Set, used to verify the signature of `id_token`s.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(transparent)]
+pub struct Jwks(JwkSet);
+
+impl Jwks {
+    /// Parses a key set, for example one cached from an earlier [`ProviderMetadata::fetch_jwks`].
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidResponse`] if `json` is not a valid JSON Web Key Set.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json)
+            .map_err(|err| Error::InvalidResponse(format!("Invalid JSON Web Key Set: {}", err)))
+    }
+}
+
+/// The claims of a validated `id_token`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct IdTokenClaims {
+    /// The issuer.
+    pub iss: String,
+    /// The user's unique identifier at the issuer.
+    pub sub: String,
+    /// The expiration time as a unix timestamp.
+    pub exp: u64,
+    /// The time the token was issued at as a unix timestamp.
+    pub iat: u64,
+    /// The nonce sent with the authorization request.
+    pub nonce: Option<String>,
+    /// The user's email address, if the `email` scope was granted.
+    pub email: Option<String>,
+    /// Whether the provider verified the email address.
+    pub email_verified: Option<bool>,
+    /// The user's full name, if the `profile` scope was granted.
+    pub name: Option<String>,
+    /// All other claims, including `aud`.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// Validates the signature, `iss`, `aud`, `exp` and `nonce` of an `id_token` and returns its claims.
+///
+/// Tokens signed with shared secret (HMAC) algorithms are rejected because their keys can't be published in a JWKS.
+///
+/// # Errors
+///
+/// - Returns [`Error::InvalidToken`] if no matching key was found or the token is invalid.
+pub fn validate_id_token(
+    id_token: &str,
+    jwks: &Jwks,
+    issuer: &str,
+    client_id: &str,
+    nonce: Option<&str>,
+) -> Result<IdTokenClaims, Error> {
+    let header = jsonwebtoken::decode_header(id_token).map_err(invalid_token)?;
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(invalid_token("HMAC signed id_tokens are not supported"));
+    }
+
+    let jwk = match &header.kid {
+        Some(kid) => jwks.0.find(kid),
+        // Without a key id the key set must be unambiguous.
+        None if jwks.0.keys.len() == 1 => jwks.0.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| invalid_token("No matching signing key found"))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(invalid_token)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[client_id]);
+    validation.set_issuer(&[issuer]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map_err(invalid_token)?
+        .claims;
+
+    if let Some(nonce) = nonce {
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(invalid_token("The nonce doesn't match"));
+        }
+    }
+
+    Ok(claims)
+}
+
+/// Generates a random `nonce` to add to the authorization request, for example via [`OauthFlow::param`].
+#[must_use]
+pub fn generate_nonce() -> String {
+    random_token()
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, Error> {
+    let value = reqwest::get(url).await?.error_for_status()?.json().await?;
+    Ok(value)
+}
+
+fn invalid_token<E: ToString>(err: E) -> Error {
+    Error::InvalidToken(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use jsonwebtoken::{EncodingKey, Header};
+
+    use super::*;
+
+    /// The PKCS#8 DER of a P-256 key generated for these tests only.
+    const PRIVATE_KEY: &str = "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg5JJC1ATLNPm7VwiN\
+        7c6IjUECG7Kp1G0PqPumZiepJfahRANCAAQM+1cZY97N0LGsaelcU2HTMlfCX/rd\
+        NfN9jYqAp42JIDf+3uLlGDZSJUNE38MMWzHEASljp/E+lsC7N4hhfs5G";
+    const ISSUER: &str = "https://issuer.example.com";
+    const CLIENT_ID: &str = "client";
+
+    fn jwks() -> Jwks {
+        Jwks::from_json(
+            r#"{ "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "x": "DPtXGWPezdCxrGnpXFNh0zJXwl_63TXzfY2KgKeNiSA",
+                "y": "N_7e4uUYNlIlQ0TfwwxbMcQBKWOn8T6WwLs3iGF-zkY",
+                "kid": "test-key",
+                "alg": "ES256",
+                "use": "sig"
+            }] }"#,
+        )
+        .unwrap()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn claims() -> serde_json::Value {
+        serde_json::json!({
+            "iss": ISSUER,
+            "aud": CLIENT_ID,
+            "sub": "user",
+            "iat": now(),
+            "exp": now() + 3600,
+            "nonce": "nonce",
+        })
+    }
+
+    fn sign(claims: &serde_json::Value, kid: &str) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(kid.to_string());
+        let key = EncodingKey::from_ec_der(&STANDARD.decode(PRIVATE_KEY).unwrap());
+        jsonwebtoken::encode(&header, claims, &key).unwrap()
+    }
+
+    fn validate(token: &str, nonce: Option<&str>) -> Result<IdTokenClaims, Error> {
+        validate_id_token(token, &jwks(), ISSUER, CLIENT_ID, nonce)
+    }
+
+    fn with(claim: &str, value: serde_json::Value) -> serde_json::Value {
+        let mut claims = claims();
+        claims[claim] = value;
+        claims
+    }
+
+    #[test]
+    fn valid_token_is_accepted() {
+        let claims = validate(&sign(&claims(), "test-key"), Some("nonce")).unwrap();
+        assert_eq!(claims.sub, "user");
+        assert_eq!(claims.nonce.as_deref(), Some("nonce"));
+    }
+
+    #[test]
+    fn invalid_claims_are_rejected() {
+        let invalid = [
+            with("iss", "https://evil.example.com".into()),
+            with("aud", "other-client".into()),
+            with("exp", (now() - 3600).into()),
+            with("nonce", "replayed".into()),
+            with("nonce", serde_json::Value::Null),
+        ];
+        for claims in invalid {
+            let token = sign(&claims, "test-key");
+            assert!(
+                matches!(validate(&token, Some("nonce")), Err(Error::InvalidToken(_))),
+                "{}",
+                claims
+            );
+        }
+    }
+
+    #[test]
+    fn hmac_token_is_rejected() {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("test-key".to_string());
+        let token =
+            jsonwebtoken::encode(&header, &claims(), &EncodingKey::from_secret(b"secret")).unwrap();
+        assert!(matches!(
+            validate(&token, Some("nonce")),
+            Err(Error::InvalidToken(message)) if message.contains("HMAC")
+        ));
+    }
+
+    #[test]
+    fn unknown_key_id_is_rejected() {
+        let token = sign(&claims(), "rotated-key");
+        assert!(matches!(
+            validate(&token, Some("nonce")),
+            Err(Error::InvalidToken(message)) if message.contains("No matching signing key")
+        ));
+    }
+}
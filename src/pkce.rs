@@ -0,0 +1,94 @@
+//! Helpers for the Proof Key for Code Exchange ([RFC 7636](https://www.rfc-editor.org/rfc/rfc7636)) extension.
+//!
+//! Public clients like desktop apps can't keep a client secret, so they should always use PKCE with the authorization code flow.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// A `code_verifier` and its S256 `code_challenge`.
+///
+/// Send the challenge with the authorization request and the verifier with the token request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PkcePair {
+    verifier: String,
+    challenge: String,
+}
+
+impl PkcePair {
+    /// Generates a new random 43 characters long `code_verifier` and the corresponding `code_challenge`.
+    #[must_use]
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let verifier = URL_SAFE_NO_PAD.encode(bytes);
+        let challenge = challenge(&verifier);
+
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+
+    /// The `code_verifier`. Keep this secret until the token request.
+    #[must_use]
+    pub fn verifier(&self) -> &str {
+        &self.verifier
+    }
+
+    /// The `code_challenge` to send with the authorization request.
+    #[must_use]
+    pub fn challenge(&self) -> &str {
+        &self.challenge
+    }
+
+    /// The `code_challenge_method`, always `"S256"`.
+    #[must_use]
+    pub fn method(&self) -> &'static str {
+        "S256"
+    }
+
+    /// Appends the `code_challenge` and `code_challenge_method` query parameters to an authorization URL.
+    pub fn append_to(&self, url: &mut url::Url) {
+        url.query_pairs_mut()
+            .append_pair("code_challenge", &self.challenge)
+            .append_pair("code_challenge_method", self.method());
+    }
+}
+
+/// Computes the S256 `code_challenge` for a `code_verifier`, i.e. `BASE64URL(SHA256(verifier))` without padding.
+#[must_use]
+pub fn challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenge_matches_rfc_7636() {
+        // Appendix B of RFC 7636.
+        assert_eq!(
+            challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9cRq7_6dTYe1yv1YnTiHylN2ZLZ0W7AKPFqc3ik-ks"
+        );
+    }
+
+    #[test]
+    fn generated_verifier_is_valid() {
+        let pair = PkcePair::generate();
+        let verifier = pair.verifier();
+        assert!((43..=128).contains(&verifier.len()), "{}", verifier);
+        // The unreserved characters of RFC 3986, the only ones allowed in a verifier.
+        assert!(
+            verifier
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c)),
+            "{}",
+            verifier
+        );
+        assert_eq!(pair.challenge(), challenge(verifier));
+        assert_ne!(PkcePair::generate(), pair);
+    }
+}
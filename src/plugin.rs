@@ -0,0 +1,600 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
+};
+
+use tauri::{
+    plugin::{Builder, TauriPlugin},
+    AppHandle, Invoke, Manager, RunEvent, Runtime, State, Window,
+};
+
+#[cfg(feature = "storage")]
+use crate::TokenStore;
+use crate::{
+    config::deserialize_millis, event_emitter, start_with_handle, Error, ErrorHandler, OauthConfig,
+    ServerHandle, ServerStatus, TimeoutHandler,
+};
+#[cfg(feature = "oauth2")]
+use crate::{random_token, OauthClient, TokenResponse};
+
+/// Defaults for the servers started via the plugin. Values set in the config passed to `start()` take precedence.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PluginConfig {
+    ports: Option<Vec<u16>>,
+    address: Option<IpAddr>,
+    response: Option<Cow<'static, str>>,
+    error_response: Option<Cow<'static, str>>,
+    response_file: Option<PathBuf>,
+    response_asset: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_millis")]
+    auto_cancel_after: Option<Duration>,
+    persistent: Option<bool>,
+    callback_path: Option<String>,
+    secret_path: Option<bool>,
+    close_tab: Option<bool>,
+}
+
+impl PluginConfig {
+    /// Parses the `plugins.oauth` section of tauri.conf.json. A string is used as the `response` page, like in earlier versions.
+    pub(crate) fn parse<'de, D: serde::Deserializer<'de> + Clone>(
+        config: D,
+    ) -> Result<Self, D::Error> {
+        // Not an untagged enum, its error wouldn't name the unknown or invalid field.
+        if let Ok(response) = <String as serde::Deserialize>::deserialize(config.clone()) {
+            return Ok(PluginConfig {
+                response: Some(response.into()),
+                ..PluginConfig::default()
+            });
+        }
+        Ok(<Option<PluginConfig> as serde::Deserialize>::deserialize(config)?.unwrap_or_default())
+    }
+
+    /// Fills in the defaults and loads the `response_asset`.
+    pub(crate) fn prepare<R: Runtime>(
+        &self,
+        app: &AppHandle<R>,
+        mut config: OauthConfig,
+    ) -> Result<OauthConfig, Error> {
+        fn or<T: Clone>(value: &mut Option<T>, default: &Option<T>) {
+            if value.is_none() {
+                *value = default.clone();
+            }
+        }
+
+        // A page set for this server replaces all of the default pages.
+        if config.response.is_none()
+            && config.response_file.is_none()
+            && config.response_asset.is_none()
+        {
+            or(&mut config.response, &self.response);
+            or(&mut config.response_file, &self.response_file);
+            or(&mut config.response_asset, &self.response_asset);
+        }
+        or(&mut config.error_response, &self.error_response);
+        or(&mut config.ports, &self.ports);
+        or(&mut config.address, &self.address);
+        or(&mut config.auto_cancel_after, &self.auto_cancel_after);
+        or(&mut config.persistent, &self.persistent);
+        or(&mut config.callback_path, &self.callback_path);
+        or(&mut config.secret_path, &self.secret_path);
+        or(&mut config.close_tab, &self.close_tab);
+
+        if let Some(asset) = config.response_asset.clone() {
+            config.load_asset_response(app, &asset)?;
+        }
+        Ok(config)
+    }
+}
+
+/// The payload of the `oauth://error` event. `kind` and `message` match the serialized [`Error`].
+#[derive(Clone, serde::Serialize)]
+struct ErrorEvent {
+    port: u16,
+    kind: &'static str,
+    message: String,
+}
+
+/// Servers started via the plugin, keyed by their port.
+#[derive(Default)]
+pub(crate) struct Servers(Mutex<HashMap<u16, ServerHandle>>);
+
+impl Servers {
+    fn lock(&self) -> MutexGuard<'_, HashMap<u16, ServerHandle>> {
+        let mut servers = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        // Servers exit on their own after a successful callback, so there is nothing left to stop.
+        servers.retain(|_, handle| !handle.is_finished());
+        servers
+    }
+
+    pub(crate) fn insert(&self, handle: ServerHandle) -> u16 {
+        let port = handle.port();
+        self.lock().insert(port, handle);
+        port
+    }
+
+    pub(crate) fn ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.lock().keys().copied().collect();
+        ports.sort_unstable();
+        ports
+    }
+
+    pub(crate) fn status(&self, port: u16) -> Result<ServerStatus, Error> {
+        self.lock()
+            .get(&port)
+            .map(ServerHandle::status)
+            .ok_or(Error::ServerNotFound(port))
+    }
+
+    pub(crate) fn redirect_uri(&self, port: u16) -> Result<String, Error> {
+        self.lock()
+            .get(&port)
+            .map(ServerHandle::redirect_uri)
+            .ok_or(Error::ServerNotFound(port))
+    }
+
+    pub(crate) fn cancel(&self, port: u16) -> Result<(), Error> {
+        let handle = self
+            .lock()
+            .remove(&port)
+            .ok_or(Error::ServerNotFound(port))?;
+        handle.stop()
+    }
+
+    /// Stops all servers, returning the last error if some of them couldn't be stopped.
+    pub(crate) fn cancel_all(&self) -> Result<(), Error> {
+        let handles: Vec<ServerHandle> = self.lock().drain().map(|(_, handle)| handle).collect();
+        for handle in &handles {
+            handle.signal_stop();
+        }
+        let mut result = Ok(());
+        for handle in handles {
+            if let Err(err) = handle.stop() {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    /// Stops all servers and waits for them to exit, so their ports are released and the handlers are dropped.
+    pub(crate) fn shutdown(&self) {
+        let handles: Vec<ServerHandle> = self.lock().drain().map(|(_, handle)| handle).collect();
+        for handle in &handles {
+            handle.signal_stop();
+        }
+        for handle in handles {
+            let port = handle.port();
+            if let Err(err) = handle.join() {
+                error!("Server on port {} exited with an error: {}", port, err);
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub(crate) fn start<R: Runtime>(
+    window: Window<R>,
+    servers: State<'_, Servers>,
+    defaults: State<'_, PluginConfig>,
+    config: Option<super::OauthConfig>,
+) -> Result<u16, Error> {
+    let mut config = defaults.prepare(&window.app_handle(), config.unwrap_or_default())?;
+    // The port is only known once the server started, which is long before any realistic timeout fires.
+    let server_port = Arc::new(AtomicU16::new(0));
+    if config.auto_cancel_after.is_some() && config.on_timeout.is_none() {
+        let window = window.clone();
+        let server_port = server_port.clone();
+        config.on_timeout = Some(TimeoutHandler::new(move || {
+            let port = server_port.load(Ordering::SeqCst);
+            if let Err(emit_err) = window.emit("oauth://timeout", port) {
+                error!("Error emitting oauth://timeout event: {}", emit_err)
+            };
+        }));
+    }
+    if config.on_error.is_none() {
+        let window = window.clone();
+        let server_port = server_port.clone();
+        config.on_error = Some(ErrorHandler::new(move |err| {
+            let payload = ErrorEvent {
+                port: server_port.load(Ordering::SeqCst),
+                kind: err.kind(),
+                message: err.to_string(),
+            };
+            if let Err(emit_err) = window.emit("oauth://error", payload) {
+                error!("Error emitting oauth://error event: {}", emit_err)
+            };
+        }));
+    }
+
+    let handle = start_with_handle(
+        config,
+        event_emitter(move |event, payload| window.emit(event, payload)),
+    )?;
+    server_port.store(handle.port(), Ordering::SeqCst);
+
+    Ok(servers.insert(handle))
+}
+
+/// Clients registered via [`Oauth::register_client`](crate::Oauth::register_client), by their name.
+#[cfg(feature = "oauth2")]
+#[derive(Default)]
+pub(crate) struct Clients(pub(crate) Mutex<HashMap<String, OauthClient>>);
+
+/// Tokens kept in Rust by the `exchange_code` command, by their session id.
+#[cfg(feature = "oauth2")]
+#[derive(Default)]
+pub(crate) struct Sessions(pub(crate) Mutex<HashMap<String, TokenResponse>>);
+
+/// The result of the `exchange_code` command, `{ "tokens": { ... } }` or `{ "session": "..." }`.
+#[cfg(feature = "oauth2")]
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Exchanged {
+    Tokens(TokenResponse),
+    Session(String),
+}
+
+#[cfg(feature = "oauth2")]
+#[tauri::command]
+pub(crate) async fn exchange_code(
+    clients: State<'_, Clients>,
+    sessions: State<'_, Sessions>,
+    client: String,
+    code: String,
+    redirect_uri: String,
+    code_verifier: Option<String>,
+    keep_tokens: Option<bool>,
+) -> Result<Exchanged, Error> {
+    let registered = clients
+        .0
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&client)
+        .cloned()
+        .ok_or_else(|| {
+            Error::InvalidConfig(format!("No client named {:?} is registered", client))
+        })?;
+    let tokens = registered
+        .exchange_code(&code, &redirect_uri, code_verifier.as_deref())
+        .await?;
+    if !keep_tokens.unwrap_or(false) {
+        return Ok(Exchanged::Tokens(tokens));
+    }
+
+    let session = random_token();
+    sessions
+        .0
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(session.clone(), tokens);
+    Ok(Exchanged::Session(session))
+}
+
+#[tauri::command]
+pub(crate) fn cancel(servers: State<'_, Servers>, port: u16) -> Result<(), Error> {
+    servers.cancel(port)
+}
+
+#[tauri::command]
+pub(crate) fn cancel_all(servers: State<'_, Servers>) -> Result<(), Error> {
+    servers.cancel_all()
+}
+
+#[tauri::command]
+pub(crate) fn list(servers: State<'_, Servers>) -> Vec<u16> {
+    servers.ports()
+}
+
+#[tauri::command]
+pub(crate) fn status(servers: State<'_, Servers>, port: u16) -> Result<ServerStatus, Error> {
+    servers.status(port)
+}
+
+#[tauri::command]
+pub(crate) fn redirect_uri(servers: State<'_, Servers>, port: u16) -> Result<String, Error> {
+    servers.redirect_uri(port)
+}
+
+/// Dispatches to the commands of the enabled features, `generate_handler!` doesn't support `cfg` attributes.
+pub(crate) fn invoke_handler<R: Runtime>() -> impl Fn(Invoke<R>) + Send + Sync + 'static {
+    let handler = server_handler();
+    #[cfg(feature = "oauth2")]
+    let handler = {
+        let oauth2 = oauth2_handler();
+        move |invoke: Invoke<R>| match invoke.message.command() {
+            "exchange_code" => oauth2(invoke),
+            _ => handler(invoke),
+        }
+    };
+    #[cfg(feature = "storage")]
+    let handler = {
+        let storage = storage_handler();
+        move |invoke: Invoke<R>| match invoke.message.command() {
+            "save_tokens" | "load_tokens" | "clear_tokens" => storage(invoke),
+            _ => handler(invoke),
+        }
+    };
+
+    handler
+}
+
+fn server_handler<R: Runtime>() -> impl Fn(Invoke<R>) + Send + Sync + 'static {
+    tauri::generate_handler![start, cancel, cancel_all, list, status, redirect_uri]
+}
+
+#[cfg(feature = "oauth2")]
+fn oauth2_handler<R: Runtime>() -> impl Fn(Invoke<R>) + Send + Sync + 'static {
+    tauri::generate_handler![exchange_code]
+}
+
+#[cfg(feature = "storage")]
+fn storage_handler<R: Runtime>() -> impl Fn(Invoke<R>) + Send + Sync + 'static {
+    tauri::generate_handler![save_tokens, load_tokens, clear_tokens]
+}
+
+#[cfg(feature = "storage")]
+fn token_store<R: Runtime>(app: &tauri::AppHandle<R>) -> TokenStore {
+    TokenStore::new(app.config().tauri.bundle.identifier.clone())
+}
+
+#[cfg(feature = "storage")]
+#[tauri::command]
+pub(crate) fn save_tokens<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    provider: String,
+    account: String,
+    tokens: TokenResponse,
+) -> Result<(), Error> {
+    token_store(&app).save_tokens(&provider, &account, &tokens)
+}
+
+#[cfg(feature = "storage")]
+#[tauri::command]
+pub(crate) fn load_tokens<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    provider: String,
+    account: String,
+) -> Result<Option<TokenResponse>, Error> {
+    token_store(&app).load_tokens(&provider, &account)
+}
+
+#[cfg(feature = "storage")]
+#[tauri::command]
+pub(crate) fn clear_tokens<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    provider: String,
+    account: String,
+) -> Result<(), Error> {
+    token_store(&app).clear_tokens(&provider, &account)?;
+    let payload = crate::LoggedOut {
+        provider: Some(provider),
+    };
+    if let Err(emit_err) = app.emit_all("oauth://logged-out", payload) {
+        error!("Error emitting oauth://logged-out event: {}", emit_err);
+    }
+    Ok(())
+}
+
+/// Initializes the tauri plugin.
+/// Only use this if you need the JavaScript APIs.
+///
+/// The plugin registers the `plugin:oauth|start`, `plugin:oauth|cancel`, `plugin:oauth|cancel_all`, `plugin:oauth|list`,
+/// `plugin:oauth|status` and `plugin:oauth|redirect_uri` commands.
+/// `start()` returns the port of the new server and emits the redirect URL to the calling window
+/// via the `oauth://url` event (or `oauth://invalid-url` if it couldn't be parsed).
+/// If `auto_cancel_after` is set, the window receives the server's port via the `oauth://timeout` event when it stopped because of that.
+/// Errors of the running server are emitted via the `oauth://error` event as `{ port, kind, message }`, see [`OauthConfig::on_error`].
+/// The other commands only see servers started via the plugin, that is via `start()` or [`Oauth::start`]:
+/// `cancel()` and `cancel_all()` stop them, `list()` returns the ports of the running ones,
+/// `status()` returns the [`ServerStatus`] of one of them
+/// and `redirect_uri()` returns the full redirect URI of one of them, including its `callback_path`.
+///
+/// Use [`OauthExt`] to manage the same servers from Rust. They are stopped when the app exits.
+///
+/// With the `oauth2` feature enabled, the plugin also registers `plugin:oauth|exchange_code`. It takes the name of a client
+/// registered via [`Oauth::register_client`], the `code`, the `redirect_uri` and an optional `code_verifier`,
+/// and returns `{ tokens }` with the [`TokenResponse`]. With `keep_tokens` set, the tokens stay in Rust
+/// and it returns `{ session }` instead, see [`Oauth::session_tokens`].
+///
+/// With the `storage` feature enabled, the plugin also registers `plugin:oauth|save_tokens`, `plugin:oauth|load_tokens`
+/// and `plugin:oauth|clear_tokens`, which use a [`TokenStore`] named after the app's bundle identifier.
+/// `clear_tokens()` emits the `oauth://logged-out` event with a [`LoggedOut`] payload to all windows, like [`TokenManager::logout`].
+/// Keep in mind that `load_tokens()` hands the tokens to the webview, prefer doing the token handling in Rust if you can.
+///
+/// The `plugins.oauth` section of tauri.conf.json sets defaults for the servers started via the plugin.
+/// It accepts the `ports`, `address`, `response`, `error_response`, `response_file`, `response_asset`,
+/// `auto_cancel_after`, `persistent`, `callback_path`, `secret_path` and `close_tab` options of [`OauthConfig`],
+/// for example `{ "ports": [8000, 8001], "response_asset": "oauth.html", "auto_cancel_after": 300000 }`.
+/// Options passed to `start()` or [`Oauth::start`] take precedence, a page passed to them replaces all of the default pages.
+/// A string instead of an object is used as the `response` page.
+#[must_use]
+pub fn init<R: Runtime>() -> TauriPlugin<R> {
+    Builder::new("oauth")
+        .invoke_handler(invoke_handler())
+        .setup(|app| {
+            let defaults: PluginConfig = match app.config().plugins.0.get("oauth") {
+                Some(config) => PluginConfig::parse(config.clone())?,
+                None => Default::default(),
+            };
+            app.manage(Servers::default());
+            app.manage(defaults);
+            #[cfg(feature = "oauth2")]
+            {
+                app.manage(Clients::default());
+                app.manage(Sessions::default());
+            }
+            Ok(())
+        })
+        .on_event(|app, event| {
+            // Not on `ExitRequested`: apps can still cancel the exit with `prevent_exit`,
+            // for example to keep running in the tray, and a login in progress must survive that.
+            // `Exit` is always emitted before the process ends.
+            if let RunEvent::Exit = event {
+                app.state::<Servers>().shutdown();
+            }
+        })
+        .build()
+}
+
+/// Extension trait to manage the servers started via the plugin from Rust, see [`init`].
+pub trait OauthExt<R: Runtime> {
+    /// Returns the plugin's server registry.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if the plugin was not initialized via [`init`].
+    fn oauth(&self) -> Oauth<R>;
+}
+
+impl<R: Runtime, T: Manager<R>> OauthExt<R> for T {
+    fn oauth(&self) -> Oauth<R> {
+        Oauth {
+            app: self.app_handle(),
+        }
+    }
+}
+
+/// The servers started via the plugin, shared with the JavaScript commands. See [`OauthExt`].
+pub struct Oauth<R: Runtime> {
+    app: AppHandle<R>,
+}
+
+impl<R: Runtime> Oauth<R> {
+    fn servers(&self) -> tauri::State<'_, Servers> {
+        self.app.state()
+    }
+
+    /// Same as [`start_with_handle`] but the server is tracked by the plugin until it exits,
+    /// so it can be stopped via [`Oauth::cancel`] or the `cancel()` command. Returns the port its listening on.
+    /// Options that are not set in `config` are taken from tauri.conf.json, see [`init`].
+    ///
+    /// # Errors
+    ///
+    /// - Returns the errors of [`start_with_handle`].
+    /// - Returns [`Error::InvalidConfig`] if the `response_asset` doesn't exist or isn't valid UTF-8.
+    pub fn start<F: FnMut(String) + Send + 'static>(
+        &self,
+        config: OauthConfig,
+        handler: F,
+    ) -> Result<u16, Error> {
+        let config = self
+            .app
+            .state::<PluginConfig>()
+            .prepare(&self.app, config)?;
+        let handle = start_with_handle(config, handler)?;
+        Ok(self.servers().insert(handle))
+    }
+
+    /// The ports of the running servers.
+    #[must_use]
+    pub fn list(&self) -> Vec<u16> {
+        self.servers().ports()
+    }
+
+    /// The status of the server on this port, see [`ServerHandle::status`].
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::ServerNotFound`] if no server started via the plugin is running on this port.
+    pub fn status(&self, port: u16) -> Result<ServerStatus, Error> {
+        self.servers().status(port)
+    }
+
+    /// The full redirect URI of the server on this port, see [`ServerHandle::redirect_uri`].
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::ServerNotFound`] if no server started via the plugin is running on this port.
+    pub fn redirect_uri(&self, port: u16) -> Result<String, Error> {
+        self.servers().redirect_uri(port)
+    }
+
+    /// Stops the server on this port.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::ServerNotFound`] if no server started via the plugin is running on this port.
+    /// - Returns the errors of [`ServerHandle::stop`].
+    pub fn cancel(&self, port: u16) -> Result<(), Error> {
+        self.servers().cancel(port)
+    }
+
+    /// Stops all servers started via the plugin.
+    ///
+    /// # Errors
+    ///
+    /// - Returns the errors of [`ServerHandle::stop`]. The remaining servers are stopped anyway.
+    pub fn cancel_all(&self) -> Result<(), Error> {
+        self.servers().cancel_all()
+    }
+
+    /// Registers a client for the `exchange_code` command under this name, replacing a client with the same name.
+    #[cfg(feature = "oauth2")]
+    pub fn register_client(&self, name: impl Into<String>, client: OauthClient) {
+        self.app
+            .state::<Clients>()
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.into(), client);
+    }
+
+    /// The tokens the `exchange_code` command kept for this session.
+    #[cfg(feature = "oauth2")]
+    #[must_use]
+    pub fn session_tokens(&self, session: &str) -> Option<TokenResponse> {
+        self.app
+            .state::<Sessions>()
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(session)
+            .cloned()
+    }
+
+    /// Forgets the tokens of this session and returns them.
+    #[cfg(feature = "oauth2")]
+    pub fn end_session(&self, session: &str) -> Option<TokenResponse> {
+        self.app
+            .state::<Sessions>()
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugin_config_errors_name_the_field() {
+        let err = PluginConfig::parse(serde_json::json!({ "prots": [8000] })).unwrap_err();
+        assert!(err.to_string().contains("prots"), "{}", err);
+        let err = PluginConfig::parse(serde_json::json!({ "ports": "8000" })).unwrap_err();
+        assert!(err.to_string().contains("invalid type"), "{}", err);
+
+        let config = PluginConfig::parse(serde_json::json!("<h1>Done</h1>")).unwrap();
+        assert!(format!("{:?}", config).contains("<h1>Done</h1>"));
+        PluginConfig::parse(serde_json::json!(null)).unwrap();
+    }
+
+    #[test]
+    fn start_command_ignores_response_file() {
+        let config: OauthConfig = serde_json::from_value(serde_json::json!({
+            "response_file": "/etc/passwd",
+            "response_asset": "oauth.html",
+        }))
+        .unwrap();
+        assert!(config.response_file.is_none());
+        assert_eq!(config.response_asset.as_deref(), Some("oauth.html"));
+    }
+}
@@ -0,0 +1,154 @@
+//! Presets for common providers, see [`OauthFlow::from_provider`].
+//!
+//! You still have to register your app with the provider and use the client id (and secret where needed) you got there.
+
+use crate::{authorization::parse_endpoint, Error, OauthFlow};
+
+/// Endpoints, default scopes and redirect quirks of a provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Provider {
+    /// The provider's name, for example to use it as a key for stored tokens.
+    pub name: &'static str,
+    /// The authorization endpoint the user is sent to.
+    pub authorization_endpoint: &'static str,
+    /// The token endpoint used to exchange the code.
+    pub token_endpoint: &'static str,
+    /// The device authorization endpoint, if the provider supports the device code flow.
+    pub device_authorization_endpoint: Option<&'static str>,
+    /// The scopes requested by default.
+    pub default_scopes: &'static [&'static str],
+    /// The host the provider expects in loopback `redirect_uri`s, if it only accepts a specific one.
+    pub redirect_host: Option<&'static str>,
+}
+
+/// Google. Register a "Desktop app" client, which may use any loopback port.
+///
+/// Google's desktop clients require the `client_secret` for the token exchange even though it can't be kept secret.
+pub const GOOGLE: Provider = Provider {
+    name: "google",
+    authorization_endpoint: "https://accounts.google.com/o/oauth2/v2/auth",
+    token_endpoint: "https://oauth2.googleapis.com/token",
+    device_authorization_endpoint: Some("https://oauth2.googleapis.com/device/code"),
+    default_scopes: &["openid", "email", "profile"],
+    redirect_host: Some("127.0.0.1"),
+};
+
+/// GitHub. Register `http://127.0.0.1/` as the callback URL, the port may differ for loopback addresses.
+///
+/// GitHub OAuth apps require the `client_secret` for the token exchange.
+pub const GITHUB: Provider = Provider {
+    name: "github",
+    authorization_endpoint: "https://github.com/login/oauth/authorize",
+    token_endpoint: "https://github.com/login/oauth/access_token",
+    device_authorization_endpoint: Some("https://github.com/login/device/code"),
+    default_scopes: &["read:user", "user:email"],
+    redirect_host: Some("127.0.0.1"),
+};
+
+/// Microsoft identity platform using the `common` tenant.
+/// Register `http://localhost` as a "Mobile and desktop applications" redirect URI, the port may differ.
+///
+/// Use [`OauthFlow::new`] with your tenant's endpoints for single-tenant apps.
+pub const MICROSOFT: Provider = Provider {
+    name: "microsoft",
+    authorization_endpoint: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize",
+    token_endpoint: "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+    device_authorization_endpoint: Some(
+        "https://login.microsoftonline.com/common/oauth2/v2.0/devicecode",
+    ),
+    default_scopes: &["openid", "profile", "email", "offline_access"],
+    redirect_host: Some("localhost"),
+};
+
+/// Discord. Discord compares redirect URIs including the port, so register a few fixed ports
+/// and pass them via [`OauthConfig::ports`](crate::OauthConfig::ports) with [`OauthFlow::config`].
+pub const DISCORD: Provider = Provider {
+    name: "discord",
+    authorization_endpoint: "https://discord.com/oauth2/authorize",
+    token_endpoint: "https://discord.com/api/oauth2/token",
+    device_authorization_endpoint: None,
+    default_scopes: &["identify", "email"],
+    redirect_host: Some("127.0.0.1"),
+};
+
+impl OauthFlow {
+    /// Creates a new flow using the endpoints, default scopes and redirect host of a [`Provider`].
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if one of the provider's endpoints is not a valid URL.
+    pub fn from_provider(provider: &Provider, client_id: impl Into<String>) -> Result<Self, Error> {
+        let flow = Self::from_endpoints(
+            client_id.into(),
+            parse_endpoint(provider.authorization_endpoint)?,
+            parse_endpoint(provider.token_endpoint)?,
+        )
+        .scopes(provider.default_scopes.iter().copied());
+
+        Ok(match provider.redirect_host {
+            Some(host) => flow.redirect_host(host),
+            None => flow,
+        })
+    }
+
+    /// Creates a flow for one of the presets, whose endpoints are checked by the tests below.
+    fn from_preset(provider: &Provider, client_id: impl Into<String>) -> Self {
+        Self::from_provider(provider, client_id).expect("invalid preset endpoint")
+    }
+
+    /// Creates a new flow for Google, see [`GOOGLE`].
+    #[must_use]
+    pub fn google(client_id: impl Into<String>) -> Self {
+        Self::from_preset(&GOOGLE, client_id)
+    }
+
+    /// Creates a new flow for GitHub, see [`GITHUB`].
+    #[must_use]
+    pub fn github(client_id: impl Into<String>) -> Self {
+        Self::from_preset(&GITHUB, client_id)
+    }
+
+    /// Creates a new flow for Microsoft, see [`MICROSOFT`].
+    #[must_use]
+    pub fn microsoft(client_id: impl Into<String>) -> Self {
+        Self::from_preset(&MICROSOFT, client_id)
+    }
+
+    /// Creates a new flow for Discord, see [`DISCORD`].
+    /// Configure fixed ports via [`OauthFlow::config`] before starting it.
+    #[must_use]
+    pub fn discord(client_id: impl Into<String>) -> Self {
+        Self::from_preset(&DISCORD, client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_endpoints_are_valid() {
+        for provider in [GOOGLE, GITHUB, MICROSOFT, DISCORD] {
+            assert!(
+                OauthFlow::from_provider(&provider, "client").is_ok(),
+                "{}",
+                provider.name
+            );
+            if let Some(endpoint) = provider.device_authorization_endpoint {
+                assert!(parse_endpoint(endpoint).is_ok(), "{}", provider.name);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_custom_provider_is_an_error() {
+        let provider = Provider {
+            token_endpoint: "not a url",
+            ..GOOGLE
+        };
+        assert!(matches!(
+            OauthFlow::from_provider(&provider, "client"),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use url::{form_urlencoded, Url};
+
+/// The parsed redirect URL of a callback.
+///
+/// Providers using the implicit flow return their parameters in the URL fragment instead of the query,
+/// so the well-known fields fall back to the fragment if they are missing in the query.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct OauthResponse {
+    /// The full URL the browser was redirected to.
+    pub url: String,
+    /// The `code` parameter of an authorization code flow.
+    pub code: Option<String>,
+    /// The `state` parameter. Compare this to the value you sent to the provider.
+    pub state: Option<String>,
+    /// The `error` parameter, for example `access_denied`.
+    pub error: Option<String>,
+    /// The human-readable `error_description` parameter.
+    pub error_description: Option<String>,
+    /// All query parameters. If a parameter appears multiple times the last value wins.
+    pub query: HashMap<String, String>,
+    /// All parameters in the URL fragment, for example `access_token` in an implicit flow.
+    pub fragment: HashMap<String, String>,
+}
+
+impl OauthResponse {
+    /// Parses the full URL received by the server.
+    ///
+    /// # Errors
+    ///
+    /// - Returns `url::ParseError` if `url` is not a valid URL.
+    pub fn parse(url: &str) -> Result<Self, url::ParseError> {
+        let parsed = Url::parse(url)?;
+
+        let query: HashMap<String, String> = parsed.query_pairs().into_owned().collect();
+        let fragment: HashMap<String, String> = parsed
+            .fragment()
+            .map(|fragment| {
+                form_urlencoded::parse(fragment.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let param = |name: &str| query.get(name).or_else(|| fragment.get(name)).cloned();
+
+        Ok(Self {
+            url: url.to_string(),
+            code: param("code"),
+            state: param("state"),
+            error: param("error"),
+            error_description: param("error_description"),
+            query,
+            fragment,
+        })
+    }
+
+    /// Returns `true` if the provider returned an `error` parameter.
+    #[must_use]
+    pub fn is_error(&self) -> bool {
+        self.error.is_some()
+    }
+}
@@ -0,0 +1,2052 @@
+use std::{
+    any::Any,
+    borrow::Cow,
+    cell::Cell,
+    collections::HashMap,
+    io::{Read, Write},
+    net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::{
+    config::{serialize_millis, CaptureMode, ResponseMode},
+    http::{self, Request, Response},
+    random_token, Error, OauthConfig, OauthResponse,
+};
+
+/// How many connections a server reads from at the same time.
+const WORKERS: usize = 4;
+/// How often an idle server checks for new connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Default upper limit for a request including its headers and body, see [`OauthConfig::max_request_size`].
+const DEFAULT_MAX_REQUEST_SIZE: usize = 64 * 1024;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Default for [`OauthConfig::handler_timeout`].
+#[cfg(feature = "tokio")]
+pub(crate) const DEFAULT_HANDLER_TIMEOUT: Duration = Duration::from_secs(30);
+/// The `Content-Type` of pages unless it's overridden by [`OauthConfig::headers`].
+const HTML_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+pub(crate) const DEFAULT_ERROR_PAGE: &str =
+    "<html><body>Login failed: {{error}}. Please return to the app.</body></html>";
+const INTERNAL_ERROR_PAGE: &str =
+    "<html><body>Something went wrong. Please return to the app.</body></html>";
+const NOT_FOUND_PAGE: &str = "<html><body>Not found.</body></html>";
+const FORBIDDEN_PAGE: &str = "<html><body>Forbidden.</body></html>";
+const GONE_PAGE: &str =
+    "<html><body>This login was already completed. Please return to the app.</body></html>";
+const INVALID_STATE_PAGE: &str =
+    "<html><body>Invalid state parameter. Please restart the login from the app.</body></html>";
+
+/// Metadata of the request that delivered a callback, see [`start_with_request_info`](crate::start_with_request_info).
+///
+/// If the parameters were captured by the page's script, this is the script's request to `/cb`
+/// and not the browser's original navigation, so `path` is `/cb` and the headers are the ones of the `fetch`.
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    /// The HTTP method, for example `GET`.
+    pub method: String,
+    /// The path including the query.
+    pub path: String,
+    /// All headers in the order they were received.
+    pub headers: Vec<(String, String)>,
+    /// The address of the client.
+    pub peer: SocketAddr,
+}
+
+impl RequestInfo {
+    fn new(request: &Request, peer: SocketAddr) -> Self {
+        Self {
+            method: request.method.clone(),
+            path: request.path.clone(),
+            headers: request.headers.clone(),
+            peer,
+        }
+    }
+
+    /// The first header with this name, compared case-insensitively.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// What the browser receives after the handler of [`start_with_response`](crate::start_with_response) ran.
+#[derive(Debug, Clone, Default)]
+pub enum HandlerResult {
+    /// The configured response, see [`OauthConfig::response`] and [`OauthConfig::response_mode`].
+    #[default]
+    Default,
+    /// A custom HTML page with this status code.
+    Page {
+        /// The HTTP status code, for example `400`.
+        status: u16,
+        /// The HTML page.
+        body: String,
+    },
+    /// Processing the callback failed for this reason. The browser gets the [`OauthConfig::error_response`] page
+    /// with a 500 status and the `server_error` error code, the reason is only passed to [`OauthConfig::on_error`]
+    /// as [`Error::HandlerFailed`].
+    ///
+    /// The callback doesn't count as handled, so persistent servers accept another attempt
+    /// even if [`OauthConfig::allow_repeated_callbacks`] is disabled. Other servers stop as usual.
+    Error(String),
+}
+
+/// Handle to a running server, see [`start_with_handle`](crate::start_with_handle).
+///
+/// Dropping the handle stops the server without executing the handler.
+pub struct ServerHandle {
+    addr: SocketAddr,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+    stop: Arc<StopSignal>,
+    fingerprint: Option<String>,
+    timed_out: Arc<AtomicBool>,
+    callback_path: String,
+    stats: Arc<Stats>,
+    /// Set by [`ServerHandle::detach`], so dropping the handle doesn't stop the server.
+    detached: bool,
+}
+
+impl ServerHandle {
+    /// The port the server is listening on.
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// The address the server is listening on.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The redirect URI pointing to the server, for example `http://127.0.0.1:1234/`.
+    /// Uses `https` if TLS is enabled and `127.0.0.1` if the server is bound to an unspecified address.
+    #[must_use]
+    pub fn redirect_uri(&self) -> String {
+        let host = match self.addr.ip() {
+            ip if ip.is_unspecified() => "127.0.0.1".to_string(),
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => format!("[{}]", ip),
+        };
+        let scheme = if self.fingerprint.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+
+        format!(
+            "{}://{}:{}{}",
+            scheme,
+            host,
+            self.addr.port(),
+            self.callback_path
+        )
+    }
+
+    /// The SHA-256 fingerprint of the self-signed certificate, formatted like `AB:CD:...`, if TLS is enabled.
+    /// Show this to users who want to verify the certificate warning of their browser.
+    #[must_use]
+    pub fn certificate_fingerprint(&self) -> Option<&str> {
+        self.fingerprint.as_deref()
+    }
+
+    /// Stops the server without executing the handler and waits until its thread exited,
+    /// so the port is released once this returns. Does nothing if the server already exited.
+    ///
+    /// A handler that is currently running is not interrupted, so this waits for it to return.
+    /// Called from the server's own handler, this doesn't wait since that would never finish.
+    /// The connections the server is currently reading from are closed, so a stalled client can't keep the server alive.
+    ///
+    /// # Errors
+    ///
+    /// This currently never fails.
+    pub fn stop(&self) -> Result<(), Error> {
+        self.stop.stop();
+        self.stop.wait_exited();
+        Ok(())
+    }
+
+    /// Blocks until the server exited, either after the handler was executed or after the server was stopped.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::HandlerPanicked`] if the server thread or one of its connection workers panicked.
+    /// - Returns [`Error::Io`] if the server thread couldn't start its connection workers.
+    pub fn join(mut self) -> Result<(), Error> {
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|payload| {
+                Err(Error::HandlerPanicked(panic_message(payload.as_ref())))
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the server thread exited, for example after the handler was executed.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().map_or(true, JoinHandle::is_finished)
+    }
+
+    /// Whether the server was stopped because of [`OauthConfig::auto_cancel_after`].
+    #[must_use]
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    /// What the server did so far, for example to show whether the browser already reached it.
+    #[must_use]
+    pub fn status(&self) -> ServerStatus {
+        ServerStatus {
+            address: self.addr,
+            uptime: self.stats.started.elapsed(),
+            connections: self.stats.connections.load(Ordering::SeqCst),
+            rejected: self.stats.rejected.load(Ordering::SeqCst),
+            callback_received: self.stats.callback_received.load(Ordering::SeqCst),
+            running: !self.is_finished(),
+        }
+    }
+
+    /// Tells the server to stop without waiting for it, see [`ServerHandle::stop`].
+    pub(crate) fn signal_stop(&self) {
+        self.stop.stop();
+    }
+
+    /// Lets the server run until it exits on its own and returns its port.
+    pub(crate) fn detach(mut self) -> u16 {
+        self.detached = true;
+        self.port()
+    }
+}
+
+/// A snapshot of a server's activity, see [`ServerHandle::status`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ServerStatus {
+    /// The address the server is listening on.
+    pub address: SocketAddr,
+    /// How long ago the server started. Serialized in milliseconds.
+    #[serde(serialize_with = "serialize_millis")]
+    pub uptime: Duration,
+    /// The number of accepted connections, including the ones that closed without sending a request.
+    pub connections: u64,
+    /// The number of requests that were answered with an error status code or couldn't be read,
+    /// for example because of a mismatching `state`, a forbidden `Host` or a repeated callback.
+    pub rejected: u64,
+    /// Whether a callback was passed to the handler.
+    pub callback_received: bool,
+    /// Whether the server is still listening.
+    pub running: bool,
+}
+
+/// Counters behind [`ServerStatus`], updated by the server thread.
+struct Stats {
+    started: Instant,
+    connections: AtomicU64,
+    rejected: AtomicU64,
+    callback_received: AtomicBool,
+}
+
+impl Stats {
+    fn reject(&self) {
+        self.rejected.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Lets other threads stop a server, even while it's waiting for a connection or reading from a stalled one.
+struct StopSignal {
+    stopped: Mutex<bool>,
+    wakeup: Condvar,
+    /// Set by the server thread right before it exits.
+    exited: Mutex<bool>,
+    exit: Condvar,
+    /// The connections the workers are currently handling, by their id.
+    connections: Mutex<HashMap<u64, TcpStream>>,
+    next_id: AtomicU64,
+}
+
+/// The signals of the running servers, so [`cancel`] can find them by their port.
+static RUNNING: Mutex<Vec<(SocketAddr, Arc<StopSignal>)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// The address of the [`StopSignal`] of the server this thread belongs to, so stopping a server
+    /// from its own handler doesn't wait for itself.
+    static CURRENT_SERVER: Cell<usize> = const { Cell::new(0) };
+}
+
+impl StopSignal {
+    fn new() -> Self {
+        Self {
+            stopped: Mutex::new(false),
+            wakeup: Condvar::new(),
+            exited: Mutex::new(false),
+            exit: Condvar::new(),
+            connections: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    fn stop(&self) {
+        *self.stopped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.wakeup.notify_all();
+        for (_, conn) in self
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain()
+        {
+            // Makes blocking reads and writes on the connection return right away.
+            let _ = conn.shutdown(net::Shutdown::Both);
+        }
+    }
+
+    /// Marks the server thread as exited and wakes up [`StopSignal::wait_exited`].
+    fn exit(&self) {
+        *self.exited.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.exit.notify_all();
+    }
+
+    /// Waits until the server thread exited, unless this is the server thread or one of its workers.
+    fn wait_exited(&self) {
+        if CURRENT_SERVER.with(Cell::get) == self.id() {
+            return;
+        }
+        let exited = self.exited.lock().unwrap_or_else(PoisonError::into_inner);
+        let _exited = self
+            .exit
+            .wait_while(exited, |exited| !*exited)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    /// Marks the current thread as belonging to this server, see [`StopSignal::wait_exited`].
+    fn enter(&self) {
+        CURRENT_SERVER.with(|current| current.set(self.id()));
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits for the next poll of the listener. Returns `true` if the server was stopped in the meantime.
+    fn wait(&self, timeout: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap_or_else(PoisonError::into_inner);
+        let (stopped, _) = self
+            .wakeup
+            .wait_timeout_while(stopped, timeout, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        *stopped
+    }
+
+    /// Registers the connection so [`StopSignal::stop`] can close it. Returns its id for [`StopSignal::untrack`],
+    /// or `None` if the server was already stopped.
+    fn track(&self, conn: &TcpStream) -> Option<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        if let Ok(conn) = conn.try_clone() {
+            self.connections
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(id, conn);
+        }
+        // Checking afterwards, so either this sees the stop or the stop sees the connection.
+        if self.is_stopped() {
+            self.untrack(id);
+            None
+        } else {
+            Some(id)
+        }
+    }
+
+    fn untrack(&self, id: u64) {
+        self.connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id);
+    }
+}
+
+impl Drop for ServerHandle {
+    // Not waiting for the thread, dropping shouldn't block, for example in async code.
+    fn drop(&mut self) {
+        if !self.detached {
+            self.signal_stop();
+        }
+    }
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err)
+)]
+pub(crate) fn spawn_server<F: FnMut(String, RequestInfo) -> HandlerResult + Send + 'static>(
+    mut config: OauthConfig,
+    handler: F,
+) -> Result<ServerHandle, Error> {
+    if let Some(target) = config.bridge.as_deref() {
+        let target = url::Url::parse(target)
+            .map_err(|err| Error::InvalidConfig(format!("Invalid bridge URL: {}", err)))?;
+        config.bridge = Some(target.to_string().into());
+    }
+    if let Some(ResponseMode::Redirect(target)) = &config.response_mode {
+        let rendered = render_url_template(target, None);
+        if rendered.contains(['\r', '\n', '\0']) || url::Url::parse(&rendered).is_err() {
+            return Err(Error::InvalidConfig(format!(
+                "Invalid redirect target {:?}",
+                target
+            )));
+        }
+    }
+    if let Some(headers) = &config.headers {
+        for (name, value) in headers {
+            validate_header(name, value)?;
+        }
+    }
+
+    if let Some(path) = &config.response_file {
+        let page = std::fs::read_to_string(path).map_err(|err| {
+            Error::InvalidConfig(format!("Couldn't read response file {:?}: {}", path, err))
+        })?;
+        config.response = Some(page.into());
+    }
+
+    let mut callback_path = config
+        .callback_path
+        .clone()
+        .unwrap_or_else(|| "/".to_string());
+    if !callback_path.starts_with('/')
+        || callback_path.contains(['?', '#'])
+        || callback_path == "/cb"
+        || (config.health_endpoint == Some(true) && callback_path == "/health")
+    {
+        return Err(Error::InvalidConfig(format!(
+            "Invalid callback path {:?}, it must start with a slash, must not contain a query or fragment and can't be /cb or /health",
+            callback_path
+        )));
+    }
+    let mut public_path = callback_path.clone();
+    if config.secret_path.unwrap_or(false) {
+        if !callback_path.ends_with('/') {
+            callback_path.push('/');
+        }
+        public_path = format!("{}[secret]", callback_path);
+        callback_path.push_str(&random_token());
+    }
+    config.callback_path = Some(callback_path.clone());
+
+    let read_timeout = config.read_timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let write_timeout = config.write_timeout.unwrap_or(DEFAULT_TIMEOUT);
+    if read_timeout.is_zero() || write_timeout.is_zero() {
+        return Err(Error::InvalidConfig(
+            "Connection timeouts must not be zero".to_string(),
+        ));
+    }
+
+    let ip = config.address.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    if !ip.is_loopback() {
+        warn!(
+            "Binding to non-loopback address {}. The server may be reachable from other machines.",
+            ip
+        );
+    }
+
+    let listener = match &config.ports {
+        Some(ports) => bind_first_free(ip, ports)?,
+        None => TcpListener::bind(SocketAddr::new(ip, 0)).map_err(Error::Bind)?,
+    };
+
+    let addr = listener.local_addr()?;
+    let mut listeners = vec![listener];
+    if config.dual_stack.unwrap_or(true) && ip.is_loopback() {
+        let other = match ip {
+            IpAddr::V4(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+        match TcpListener::bind(SocketAddr::new(other, addr.port())) {
+            Ok(listener) => listeners.push(listener),
+            Err(err) => debug!(
+                "Only listening on {}, couldn't bind to {}: {}",
+                addr, other, err
+            ),
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    let tls = match config.tls {
+        Some(true) => Some(tls::ServerTls::new(ip)?),
+        _ => None,
+    };
+    #[cfg(feature = "tls")]
+    let fingerprint = tls.as_ref().map(|tls| tls.fingerprint().to_string());
+    #[cfg(not(feature = "tls"))]
+    let fingerprint = None;
+
+    // Polling the listeners instead of blocking in `accept`, so the server notices when it's stopped.
+    for listener in &listeners {
+        listener.set_nonblocking(true)?;
+    }
+    let stop = Arc::new(StopSignal::new());
+
+    let stats = Arc::new(Stats {
+        started: Instant::now(),
+        connections: AtomicU64::new(0),
+        rejected: AtomicU64::new(0),
+        callback_received: AtomicBool::new(false),
+    });
+    let timed_out = Arc::new(AtomicBool::new(false));
+    // Dropped by the server thread when it exits, which wakes up the timeout thread early.
+    let (alive, exited) = mpsc::channel::<()>();
+    if let Some(timeout) = config.auto_cancel_after {
+        let timed_out = timed_out.clone();
+        let stop = stop.clone();
+        thread::Builder::new()
+            .name(format!("oauth-timeout-{}", addr.port()))
+            .spawn(move || {
+                if exited.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    stop.stop();
+                }
+            })?;
+    }
+
+    let server_timed_out = timed_out.clone();
+    let server_stop = stop.clone();
+    let exit_stop = stop.clone();
+    let server_stats = stats.clone();
+    let on_panic = config.on_error.clone();
+    RUNNING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push((addr, stop.clone()));
+    debug!("Listening on {}", addr);
+    let spawned = thread::Builder::new()
+        .name(format!("oauth-server-{}", addr.port()))
+        .spawn(move || {
+            exit_stop.enter();
+            // The handler's panics are already answered with an error page, this catches the ones of the server itself.
+            let result = panic::catch_unwind(AssertUnwindSafe(move || {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::info_span!("oauth_server", port = addr.port()).entered();
+                let server = Arc::new(Server {
+                    persistent: config.persistent.unwrap_or(false),
+                    allow_repeated: config.allow_repeated_callbacks.unwrap_or(false),
+                    config,
+                    handler: Mutex::new(handler),
+                    stop: server_stop,
+                    stats: server_stats,
+                    #[cfg(feature = "tls")]
+                    tls,
+                    read_timeout,
+                    write_timeout,
+                    alive: Mutex::new(Some(alive)),
+                    handled: AtomicBool::new(false),
+                    public_path,
+                    #[cfg(feature = "tracing")]
+                    span: tracing::Span::current(),
+                });
+
+                // The connections are read by a small pool of workers, so a stalled connection like an unused
+                // browser preconnect doesn't delay the callback. The handler is still executed for one callback at a time.
+                let (jobs, queue) = mpsc::channel::<(TcpStream, SocketAddr)>();
+                let queue = Arc::new(Mutex::new(queue));
+                let workers = (0..WORKERS)
+                    .map(|i| {
+                        let server = server.clone();
+                        let queue = queue.clone();
+                        thread::Builder::new()
+                            .name(format!("oauth-worker-{}-{}", addr.port(), i))
+                            .spawn(move || {
+                                server.stop.enter();
+                                loop {
+                                    let job =
+                                        queue.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                                    match job {
+                                        Ok((conn, peer)) => server.handle_connection(conn, peer),
+                                        Err(_) => break,
+                                    }
+                                }
+                            })
+                    })
+                    .collect::<Result<Vec<JoinHandle<()>>, _>>()?;
+
+                loop {
+                    if server.stop.is_stopped() {
+                        if server_timed_out.load(Ordering::SeqCst) {
+                            if let Some(on_timeout) = &server.config.on_timeout {
+                                (on_timeout.0)();
+                            }
+                        }
+                        break;
+                    }
+                    match accept_any(&listeners) {
+                        Ok((conn, peer)) => {
+                            server.stats.connections.fetch_add(1, Ordering::SeqCst);
+                            // The workers only exit once `jobs` is dropped.
+                            let _ = jobs.send((conn, peer));
+                        }
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                            server.stop.wait(POLL_INTERVAL);
+                        }
+                        Err(err) => report_error(&server.config, err.into()),
+                    }
+                }
+
+                // Queued connections are dropped by the workers since the server is stopped.
+                drop(jobs);
+                let mut result = Ok(());
+                for worker in workers {
+                    if let Err(payload) = worker.join() {
+                        result = Err(Error::HandlerPanicked(panic_message(payload.as_ref())));
+                    }
+                }
+                result
+            }))
+            .unwrap_or_else(|payload| Err(Error::HandlerPanicked(panic_message(payload.as_ref()))));
+
+            if let Err(err) = &result {
+                error!("Server on {} failed: {}", addr, err);
+            }
+            if let (Err(Error::HandlerPanicked(message)), Some(on_error)) = (&result, &on_panic) {
+                (on_error.0)(Error::HandlerPanicked(message.clone()));
+            }
+            RUNNING
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|(_, running)| !Arc::ptr_eq(running, &exit_stop));
+            exit_stop.exit();
+            result
+        });
+    let thread = match spawned {
+        Ok(thread) => thread,
+        Err(err) => {
+            RUNNING
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|(_, running)| !Arc::ptr_eq(running, &stop));
+            return Err(err.into());
+        }
+    };
+
+    Ok(ServerHandle {
+        addr,
+        thread: Some(thread),
+        stop,
+        fingerprint,
+        timed_out,
+        callback_path,
+        stats,
+        detached: false,
+    })
+}
+
+/// State shared by a server's thread and its connection workers.
+struct Server<F> {
+    config: OauthConfig,
+    handler: Mutex<F>,
+    stop: Arc<StopSignal>,
+    stats: Arc<Stats>,
+    #[cfg(feature = "tls")]
+    tls: Option<tls::ServerTls>,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    persistent: bool,
+    allow_repeated: bool,
+    /// Dropped after the first accepted callback, which stops the `auto_cancel_after` timer.
+    alive: Mutex<Option<mpsc::Sender<()>>>,
+    /// Whether a callback was accepted, see [`OauthConfig::allow_repeated_callbacks`].
+    handled: AtomicBool,
+    /// The callback path shown by the `/health` endpoint.
+    public_path: String,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl<F: FnMut(String, RequestInfo) -> HandlerResult> Server<F> {
+    fn handle_connection(&self, conn: TcpStream, peer: SocketAddr) {
+        #[cfg(feature = "tracing")]
+        let (span, accepted) = (
+            tracing::debug_span!(
+                parent: &self.span,
+                "connection",
+                %peer,
+                path = tracing::field::Empty,
+                outcome = tracing::field::Empty
+            )
+            .entered(),
+            Instant::now(),
+        );
+        // Accepted connections inherit the non-blocking mode on some platforms.
+        if let Err(err) = conn
+            .set_nonblocking(false)
+            .and_then(|_| conn.set_read_timeout(Some(self.read_timeout)))
+            .and_then(|_| conn.set_write_timeout(Some(self.write_timeout)))
+        {
+            report_error(&self.config, err.into());
+            return;
+        }
+        let id = match self.stop.track(&conn) {
+            Some(id) => id,
+            None => return,
+        };
+        #[cfg(feature = "tls")]
+        let conn = match &self.tls {
+            Some(tls) => tls.accept(conn).map_err(Error::from),
+            None => Ok(Connection::Plain(conn)),
+        };
+        #[cfg(not(feature = "tls"))]
+        let conn: Result<Connection, Error> = Ok(Connection::Plain(conn));
+
+        match conn {
+            Ok(mut conn) => {
+                let outcome = receive(&mut conn, peer, &self.config);
+                #[cfg(feature = "tracing")]
+                span.record("outcome", outcome.as_ref().map_or("error", Outcome::name));
+                self.respond(&mut conn, outcome);
+            }
+            Err(err) => report_error(&self.config, err),
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            elapsed_ms = accepted.elapsed().as_millis() as u64,
+            "Handled connection"
+        );
+        self.stop.untrack(id);
+    }
+
+    fn respond(&self, conn: &mut Connection, outcome: Result<Outcome, Error>) {
+        let config = &self.config;
+        match outcome {
+            Ok(Outcome::Callback { url, reply, info }) => {
+                let mut handler = self.handler.lock().unwrap_or_else(PoisonError::into_inner);
+                if self.handled.load(Ordering::SeqCst) && !self.allow_repeated {
+                    drop(handler);
+                    self.stats.reject();
+                    let _ = reply
+                        .finish(html_response(410, config, GONE_PAGE))
+                        .write_to(conn);
+                    report_error(config, Error::CallbackReplayed);
+                    return;
+                }
+                let (response, handled) = run_handler(&mut *handler, config, url, reply, info);
+                if handled == Handled::Accepted {
+                    self.handled.store(true, Ordering::SeqCst);
+                    self.stats.callback_received.store(true, Ordering::SeqCst);
+                    // `auto_cancel_after` only applies until the first callback arrived.
+                    self.alive
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .take();
+                } else {
+                    self.stats.reject();
+                }
+                drop(handler);
+
+                // The callback was captured, so a browser that went away in the meantime doesn't fail the flow.
+                // If the server was stopped while the handler ran, the connection is already closed.
+                if !self.stop.is_stopped() {
+                    if let Err(err) = response.write_to(conn) {
+                        report_error(config, err.into());
+                    }
+                }
+                // A one-shot server is done once the handler ran, even if it failed: the code was
+                // consumed or the provider answered with an error, retrying needs a new authorization
+                // request anyway. Rejected callbacks didn't reach the handler, so the real one may
+                // still arrive; errors of single connections never get here and keep it listening too.
+                if handled != Handled::Rejected && !self.persistent {
+                    self.stop.stop();
+                }
+            }
+            Ok(Outcome::Respond(response)) => {
+                if response.status >= 400 {
+                    self.stats.reject();
+                }
+                if let Err(err) = response.write_to(conn) {
+                    report_error(config, err.into());
+                }
+            }
+            Ok(Outcome::Reject(response, err)) => {
+                self.stats.reject();
+                // The error is more interesting than a failure to tell the client about it.
+                let _ = response.write_to(conn);
+                report_error(config, err);
+            }
+            Ok(Outcome::Health { head_only }) => {
+                let body = format!(
+                    r#"{{"version":{},"callback_path":{},"callback_received":{}}}"#,
+                    js_string(env!("CARGO_PKG_VERSION")),
+                    js_string(&self.public_path),
+                    self.handled.load(Ordering::SeqCst)
+                );
+                let mut response = bytes_response(200, config, "application/json", body.as_bytes());
+                if head_only {
+                    response = response.without_body();
+                }
+                if let Err(err) = response.write_to(conn) {
+                    report_error(config, err.into());
+                }
+            }
+            Ok(Outcome::Closed) => {}
+            // Reading from a connection closed by `stop` fails, that's expected.
+            Err(_) if self.stop.is_stopped() => {}
+            Err(err) => {
+                self.stats.reject();
+                report_error(config, err);
+            }
+        }
+    }
+}
+
+/// An accepted connection, optionally wrapped in TLS.
+pub(crate) enum Connection {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ServerConnection, TcpStream>>),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Plain(conn) => conn.read(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(conn) => conn.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Plain(conn) => conn.write(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(conn) => conn.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Plain(conn) => conn.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(conn) => conn.flush(),
+        }
+    }
+}
+
+/// Binds to the first port in `ports` that is available, trying them in order.
+/// Accepts a connection from the first listener that has one pending.
+/// Returns [`std::io::ErrorKind::WouldBlock`] if none of them has.
+fn accept_any(listeners: &[TcpListener]) -> std::io::Result<(TcpStream, SocketAddr)> {
+    for listener in listeners {
+        match listener.accept() {
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {}
+            result => return result,
+        }
+    }
+    Err(std::io::ErrorKind::WouldBlock.into())
+}
+
+fn bind_first_free(ip: IpAddr, ports: &[u16]) -> Result<TcpListener, Error> {
+    let mut last_err = None;
+    for port in ports {
+        match TcpListener::bind(SocketAddr::new(ip, *port)) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => {
+                debug!("Couldn't bind to port {}: {}", port, err);
+                last_err = Some(err);
+            }
+        }
+    }
+
+    Err(match last_err {
+        Some(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+            Error::NoFreePort(ports.to_vec())
+        }
+        Some(err) => Error::Bind(err),
+        None => Error::InvalidConfig("The list of configured ports is empty".to_string()),
+    })
+}
+
+/// What became of a captured callback, see [`run_handler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Handled {
+    /// The validator rejected the callback, the handler didn't run.
+    Rejected,
+    /// The handler returned [`HandlerResult::Error`].
+    Failed,
+    Accepted,
+}
+
+/// Runs the validator and the handler for a captured callback. Returns the response and what became of the callback.
+fn run_handler<F: FnMut(String, RequestInfo) -> HandlerResult>(
+    handler: &mut F,
+    config: &OauthConfig,
+    url: String,
+    reply: Reply,
+    info: RequestInfo,
+) -> (Response, Handled) {
+    // Unparseable URLs are left to the handler, like without a validator.
+    if let (Some(validator), Ok(response)) = (&config.validator, OauthResponse::parse(&url)) {
+        match panic::catch_unwind(AssertUnwindSafe(|| (validator.0)(&response))) {
+            Ok(Ok(())) => {}
+            Ok(Err(reason)) => {
+                warn!("Rejected callback: {}", reason);
+                let rejected = OauthResponse {
+                    error: Some(reason.error),
+                    error_description: reason.description,
+                    ..response
+                };
+                let page =
+                    response_page(config, Some(&rejected), info.header("Accept-Language"), "");
+                return (
+                    reply.finish(html_response(400, config, &page)),
+                    Handled::Rejected,
+                );
+            }
+            Err(payload) => {
+                report_error(
+                    config,
+                    Error::HandlerPanicked(panic_message(payload.as_ref())),
+                );
+                return (
+                    reply.finish(html_response(500, config, INTERNAL_ERROR_PAGE)),
+                    Handled::Rejected,
+                );
+            }
+        }
+    }
+
+    let accept_language = info.header("Accept-Language").map(ToString::to_string);
+    let result = match panic::catch_unwind(AssertUnwindSafe(|| handler(url.clone(), info))) {
+        Ok(result) => result,
+        Err(payload) => {
+            report_error(
+                config,
+                Error::HandlerPanicked(panic_message(payload.as_ref())),
+            );
+            HandlerResult::Page {
+                status: 500,
+                body: INTERNAL_ERROR_PAGE.to_string(),
+            }
+        }
+    };
+    let handled = if let HandlerResult::Error(reason) = &result {
+        report_error(config, Error::HandlerFailed(reason.clone()));
+        Handled::Failed
+    } else {
+        Handled::Accepted
+    };
+    (
+        reply.response(config, &url, accept_language.as_deref(), result),
+        handled,
+    )
+}
+
+/// Reads a request from the connection and decides how to answer it.
+fn receive(
+    conn: &mut Connection,
+    peer: SocketAddr,
+    config: &OauthConfig,
+) -> Result<Outcome, Error> {
+    if !config.allow_remote_peers.unwrap_or(false) && !is_loopback(peer.ip()) {
+        return Ok(Outcome::Reject(
+            html_response(403, config, FORBIDDEN_PAGE),
+            Error::Forbidden(format!(
+                "Connection from non-loopback address {}",
+                peer.ip()
+            )),
+        ));
+    }
+
+    let max_size = config.max_request_size.unwrap_or(DEFAULT_MAX_REQUEST_SIZE);
+    let deadline = Instant::now() + config.read_timeout.unwrap_or(DEFAULT_TIMEOUT);
+    match http::read_request(conn, max_size, deadline) {
+        Ok(Some(request)) if request.method == "HEAD" => {
+            Ok(without_body(handle_request(&request, peer, config), config))
+        }
+        Ok(Some(request)) => Ok(handle_request(&request, peer, config)),
+        // Browsers open speculative connections they close again without sending anything.
+        Ok(None) => Ok(Outcome::Closed),
+        Err(err @ Error::RequestTooLarge(_)) => {
+            Ok(Outcome::Reject(html_response(413, config, ""), err))
+        }
+        Err(err @ Error::InvalidRequest(_)) => {
+            Ok(Outcome::Reject(html_response(400, config, ""), err))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Decides how to answer a request.
+fn handle_request(request: &Request, peer: SocketAddr, config: &OauthConfig) -> Outcome {
+    // Only the route, the query contains secrets like the code.
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("path", request.path.split('?').next().unwrap_or_default());
+    let host = request.header("Host").unwrap_or_default();
+    let origin = request.header("Origin");
+    let host_allowed = match &config.host_validator {
+        Some(validator) => (validator.0)(host, origin),
+        None => default_host_allowed(host, config),
+    };
+    if !host_allowed {
+        return Outcome::Reject(
+            html_response(403, config, FORBIDDEN_PAGE),
+            Error::Forbidden(format!(
+                "Request with Host {:?} and Origin {:?}",
+                host, origin
+            )),
+        );
+    }
+
+    let path = request.path.as_str();
+    let route = path.split('?').next().unwrap_or_default();
+    let allowed = allowed_methods(route, config);
+    if request.method == "OPTIONS" {
+        let response = bytes_response(204, config, HTML_CONTENT_TYPE, b"").header("Allow", allowed);
+        // The `Full-Url` header makes browsers send a preflight request before the `/cb` fetch.
+        if route == "/cb" {
+            return Outcome::Respond(
+                with_cors(response, &own_origin(request, config))
+                    .header("Access-Control-Allow-Methods", "GET")
+                    .header("Access-Control-Max-Age", "600"),
+            );
+        }
+        return Outcome::Respond(response);
+    }
+    if !allowed.split(", ").any(|method| method == request.method) {
+        return Outcome::Respond(html_response(405, config, "").header("Allow", allowed));
+    }
+    if route == "/cb" {
+        let url = match request.header("Full-Url") {
+            Some(url) => url,
+            None => {
+                return Outcome::Reject(
+                    html_response(400, config, ""),
+                    Error::InvalidRequest(
+                        "Client fetched callback path but the request didn't contain the expected header."
+                            .to_string(),
+                    ),
+                )
+            }
+        };
+        let origin = own_origin(request, config);
+        // The header is set by the page served for the callback path, so any other path means it was forged.
+        let url_route = url::Url::parse(url).ok().map(|url| url.path().to_string());
+        if !secret_matches(
+            config.callback_path.as_deref().unwrap_or("/"),
+            url_route.as_deref(),
+        ) {
+            return Outcome::Reject(
+                with_cors(html_response(403, config, FORBIDDEN_PAGE), &origin),
+                Error::Forbidden(
+                    "The fetched callback URL doesn't match the callback path".to_string(),
+                ),
+            );
+        }
+        if !state_accepted(config, url) {
+            return Outcome::Reject(
+                with_cors(html_response(400, config, INVALID_STATE_PAGE), &origin),
+                Error::StateMismatch,
+            );
+        }
+        return Outcome::Callback {
+            url: url.to_string(),
+            reply: Reply::Fetch(origin),
+            info: RequestInfo::new(request, peer),
+        };
+    }
+
+    if route == "/health" && config.health_endpoint == Some(true) {
+        return Outcome::Health { head_only: false };
+    }
+    if let Some(resource) = config
+        .resources
+        .as_ref()
+        .and_then(|resources| resources.get(route))
+    {
+        return Outcome::Respond(bytes_response(
+            200,
+            config,
+            &resource.content_type,
+            &resource.body,
+        ));
+    }
+    if !secret_matches(config.callback_path.as_deref().unwrap_or("/"), Some(route)) {
+        return Outcome::Respond(html_response(404, config, NOT_FOUND_PAGE));
+    }
+
+    // `response_mode=form_post`, for example used by Sign in with Apple, sends the params as the request body.
+    // There is no fragment to capture so the URL can be reconstructed right away.
+    if request.method == "POST" {
+        let is_form = request.header("Content-Type").is_some_and(|content_type| {
+            content_type.starts_with("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return Outcome::Respond(html_response(415, config, ""));
+        }
+
+        let params = String::from_utf8_lossy(&request.body).replace('#', "%23");
+        let url = format!(
+            "http://{}{}{}{}",
+            if host.is_empty() { "localhost" } else { host },
+            path,
+            if path.contains('?') { '&' } else { '?' },
+            params
+        );
+        if !state_accepted(config, &url) {
+            return Outcome::Reject(
+                html_response(400, config, INVALID_STATE_PAGE),
+                Error::StateMismatch,
+            );
+        }
+
+        let reply = match &config.response_mode {
+            // 303 makes the browser follow the redirect with a GET request.
+            Some(ResponseMode::Redirect(_)) => Reply::Redirect(303),
+            _ => Reply::Page,
+        };
+        return Outcome::Callback {
+            url,
+            reply,
+            info: RequestInfo::new(request, peer),
+        };
+    }
+
+    // Reject mismatching state values early if they are part of the query,
+    // so the user doesn't get to see the success page at all.
+    if let Some(expected) = config.state.as_deref() {
+        let query_state = url::Url::parse(&format!("http://localhost{}", path))
+            .ok()
+            .and_then(|url| {
+                url.query_pairs()
+                    .find(|(name, _)| name == "state")
+                    .map(|(_, value)| value.into_owned())
+            });
+        if let Some(state) = query_state {
+            if !secret_matches(expected, Some(&state)) {
+                return Outcome::Reject(
+                    html_response(400, config, INVALID_STATE_PAGE),
+                    Error::StateMismatch,
+                );
+            }
+        }
+    }
+
+    // Only the query is available here, the fragment never reaches the server.
+    let response = OauthResponse::parse(&format!("http://localhost{}", path)).ok();
+    let redirect = matches!(config.response_mode, Some(ResponseMode::Redirect(_)));
+    if redirect || matches!(config.capture, Some(CaptureMode::QueryOnly)) {
+        // Callbacks with a fragment still need the page to capture it.
+        if response.as_ref().is_some_and(|r| !r.query.is_empty()) {
+            let url = format!(
+                "http://{}{}",
+                if host.is_empty() { "localhost" } else { host },
+                path
+            );
+            if !state_accepted(config, &url) {
+                return Outcome::Reject(
+                    html_response(400, config, INVALID_STATE_PAGE),
+                    Error::StateMismatch,
+                );
+            }
+            let reply = if redirect {
+                Reply::Redirect(302)
+            } else {
+                Reply::Page
+            };
+            return Outcome::Callback {
+                url,
+                reply,
+                info: RequestInfo::new(request, peer),
+            };
+        }
+    }
+
+    // Using a relative URL so the callback reaches the same host the browser used for the redirect,
+    // no matter which address the server is bound to and which of the `dual_stack` listeners `localhost` resolved to. If the handler answered with a custom page, it replaces this one.
+    let fetch = format!(
+        r#"fetch("/cb",{{headers:{{"Full-Url":window.location.href}}}}).then(r=>r.text()).then(b=>{{if(b){{document.open();document.write(b);document.close()}}else{{{}}}}}).catch(()=>{{}})"#,
+        close_tab_script(config)
+    );
+    let mut after_fetch = String::new();
+    if let Some(target) = config.bridge.as_deref() {
+        // Merge the query and fragment params and hand them off to the custom scheme once the
+        // loopback capture finished (or failed), so the deep link always fires.
+        after_fetch.push_str(&format!(
+            r#"const t={};const p=new URLSearchParams(window.location.search);new URLSearchParams(window.location.hash.slice(1)).forEach((v,k)=>p.append(k,v));window.location.replace(t+(t.includes("?")?"&":"?")+p.toString());"#,
+            js_string(target)
+        ));
+    }
+    let script = if after_fetch.is_empty() {
+        format!("<script>{}</script>", fetch)
+    } else {
+        format!(
+            "<script>{}.finally(()=>{{{}}})</script>",
+            fetch, after_fetch
+        )
+    };
+
+    Outcome::Respond(html_response(
+        200,
+        config,
+        &response_page(
+            config,
+            response.as_ref(),
+            request.header("Accept-Language"),
+            &script,
+        ),
+    ))
+}
+
+/// The methods a route can be requested with, as listed in the `Allow` header.
+fn allowed_methods(route: &str, config: &OauthConfig) -> &'static str {
+    if route == "/cb" {
+        "GET, OPTIONS"
+    } else if secret_matches(config.callback_path.as_deref().unwrap_or("/"), Some(route)) {
+        // `POST` is used by `response_mode=form_post`.
+        "GET, HEAD, POST, OPTIONS"
+    } else {
+        "GET, HEAD, OPTIONS"
+    }
+}
+
+/// Strips the body of the answer to a `HEAD` request.
+/// Browsers send those without the user being involved, for example to prefetch a page, so they never capture a callback.
+fn without_body(outcome: Outcome, config: &OauthConfig) -> Outcome {
+    match outcome {
+        Outcome::Respond(response) => Outcome::Respond(response.without_body()),
+        Outcome::Reject(response, err) => Outcome::Reject(response.without_body(), err),
+        Outcome::Health { .. } => Outcome::Health { head_only: true },
+        Outcome::Callback { .. } => Outcome::Respond(html_response(200, config, "").without_body()),
+        outcome => outcome,
+    }
+}
+
+/// How to answer a request, see [`handle_request`].
+enum Outcome {
+    /// Answer with this response. The server keeps listening.
+    Respond(Response),
+    /// Answer with this response and report the error. The server keeps listening.
+    Reject(Response, Error),
+    /// Answer with the `/health` status, or only its headers for `HEAD` requests.
+    Health { head_only: bool },
+    /// The client closed the connection without sending a request.
+    Closed,
+    /// A callback was captured. The response is created after the handler ran.
+    Callback {
+        url: String,
+        reply: Reply,
+        info: RequestInfo,
+    },
+}
+
+#[cfg(feature = "tracing")]
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Respond(_) => "respond",
+            Outcome::Reject(..) => "reject",
+            Outcome::Health { .. } => "health",
+            Outcome::Closed => "closed",
+            Outcome::Callback { .. } => "callback",
+        }
+    }
+}
+
+/// How a captured callback is answered if the handler returned [`HandlerResult::Default`].
+enum Reply {
+    /// The `/cb` fetch of the page, which already shows the response page. Contains the origin allowed to read the response.
+    Fetch(String),
+    /// The response page, used for `form_post` callbacks.
+    Page,
+    /// A redirect to the [`ResponseMode::Redirect`] target with this status.
+    Redirect(u16),
+}
+
+impl Reply {
+    fn response(
+        self,
+        config: &OauthConfig,
+        url: &str,
+        accept_language: Option<&str>,
+        result: HandlerResult,
+    ) -> Response {
+        let response = OauthResponse::parse(url).ok();
+        match result {
+            HandlerResult::Page { status, body } => {
+                return self.finish(html_response(status, config, &body));
+            }
+            HandlerResult::Error(_) => {
+                // The reason may contain internals, so the page only gets a generic error code.
+                let page = match response {
+                    Some(response) => {
+                        let failed = OauthResponse {
+                            error: Some("server_error".to_string()),
+                            error_description: None,
+                            ..response
+                        };
+                        response_page(config, Some(&failed), accept_language, "")
+                    }
+                    None => DEFAULT_ERROR_PAGE.replace("{{error}}", "server_error"),
+                };
+                return self.finish(html_response(500, config, &page));
+            }
+            HandlerResult::Default => {}
+        }
+
+        match (self, &config.response_mode) {
+            (Reply::Redirect(status), Some(ResponseMode::Redirect(target))) => redirect_response(
+                status,
+                config,
+                &render_url_template(target, response.as_ref()),
+            ),
+            // The page closes the tab if the response is empty.
+            (Reply::Fetch(origin), _) => {
+                with_cors(bytes_response(204, config, HTML_CONTENT_TYPE, b""), &origin)
+            }
+            _ => html_response(
+                200,
+                config,
+                &response_page(
+                    config,
+                    response.as_ref(),
+                    accept_language,
+                    &close_tab_tag(config),
+                ),
+            ),
+        }
+    }
+
+    /// Adds the CORS headers to responses of the `/cb` fetch.
+    fn finish(&self, response: Response) -> Response {
+        match self {
+            Reply::Fetch(origin) => with_cors(response, origin),
+            _ => response,
+        }
+    }
+}
+
+/// The origin of the pages served by this server, as the browser addressed it.
+#[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+fn own_origin(request: &Request, config: &OauthConfig) -> String {
+    #[cfg(feature = "tls")]
+    let scheme = if config.tls == Some(true) {
+        "https"
+    } else {
+        "http"
+    };
+    #[cfg(not(feature = "tls"))]
+    let scheme = "http";
+    format!(
+        "{}://{}",
+        scheme,
+        request.header("Host").unwrap_or("localhost")
+    )
+}
+
+/// Lets the page's own origin read the response of its `/cb` fetch. Other origins are still blocked by the browser,
+/// so they can't send the `Full-Url` header.
+fn with_cors(response: Response, origin: &str) -> Response {
+    response
+        .header("Access-Control-Allow-Origin", origin)
+        .header("Access-Control-Allow-Headers", "Full-Url")
+        .header("Vary", "Origin")
+}
+
+/// Accepts loopback hosts and the address the server is bound to, to protect against DNS rebinding.
+/// Servers bound to an unspecified address accept any host because the machine's hostnames aren't known.
+fn default_host_allowed(host: &str, config: &OauthConfig) -> bool {
+    let address = config.address.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    if address.is_unspecified() {
+        return true;
+    }
+    // Strip the port, keeping the brackets of IPv6 addresses.
+    let name = match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') => &host[..index],
+        _ => host,
+    };
+    let ip = name.trim_start_matches('[').trim_end_matches(']');
+    name.eq_ignore_ascii_case("localhost")
+        || ip
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| is_loopback(ip) || ip == address)
+}
+
+/// Like [`IpAddr::is_loopback`], but also accepts IPv4 loopback addresses mapped to IPv6 by dual-stack sockets.
+fn is_loopback(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map_or(ip.is_loopback(), |ip| ip.is_loopback()),
+        ip => ip.is_loopback(),
+    }
+}
+
+/// Logs an error of a running server and passes it to [`OauthConfig::on_error`].
+fn report_error(config: &OauthConfig, err: Error) {
+    #[cfg(feature = "tracing")]
+    tracing::error!(error = %err, kind = err.kind(), "Error handling incoming connection");
+    #[cfg(not(feature = "tracing"))]
+    log::error!("Error handling incoming connection: {}", err);
+    if let Some(on_error) = &config.on_error {
+        (on_error.0)(err);
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Unknown panic payload".to_string())
+}
+
+/// Builds the configured (or default) response page for the browser's `Accept-Language` header
+/// with `script` injected into its head.
+fn response_page(
+    config: &OauthConfig,
+    response: Option<&OauthResponse>,
+    accept_language: Option<&str>,
+    script: &str,
+) -> String {
+    let page = match response {
+        Some(response) if response.is_error() => Some(
+            localized(config.localized_error_responses.as_ref(), accept_language)
+                .or(config.error_response.as_deref())
+                .unwrap_or(DEFAULT_ERROR_PAGE),
+        ),
+        _ => localized(config.localized_responses.as_ref(), accept_language)
+            .or(config.response.as_deref()),
+    }
+    .map(|page| render_template(page, response));
+
+    match page.as_deref() {
+        Some(s) if s.contains("<head>") => s.replace("<head>", &format!("<head>{}", script)),
+        Some(s) if s.contains("<body>") => {
+            s.replace("<body>", &format!("<head>{}</head><body>", script))
+        }
+        Some(s) => {
+            warn!(
+                "`response` does not contain a body or head element. Prepending a head element..."
+            );
+            format!("<head>{}</head>{}", script, s)
+        }
+        None => format!(
+            "<html><head>{}</head><body>Please return to the app.</body></html>",
+            script
+        ),
+    }
+}
+
+/// Picks the page for the most preferred language of an `Accept-Language` header like `de-AT,de;q=0.9,en;q=0.5`.
+fn localized<'a>(
+    pages: Option<&'a HashMap<String, Cow<'static, str>>>,
+    accept_language: Option<&str>,
+) -> Option<&'a str> {
+    let (pages, accept_language) = (pages?, accept_language?);
+    let mut languages: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.trim().parse().ok()?,
+                None => 1.0,
+            };
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    // The sort is stable, so languages with the same quality keep the browser's order.
+    languages.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    let find = |tag: &str| {
+        pages
+            .iter()
+            .find(|(language, _)| language.eq_ignore_ascii_case(tag))
+            .map(|(_, page)| page.as_ref())
+    };
+    languages
+        .iter()
+        .find_map(|(tag, _)| find(tag).or_else(|| find(tag.split('-').next().unwrap_or(tag))))
+}
+
+/// Closes the tab if `close_tab` is enabled. Browsers only allow this for tabs opened by a script,
+/// so the page content is replaced with a hint in case the tab stays open.
+/// The delay gives `bridge` deep links time to fire.
+fn close_tab_script(config: &OauthConfig) -> &'static str {
+    if config.close_tab.unwrap_or(false) {
+        r#"setTimeout(()=>{window.close();document.body.innerText="You can close this tab now."},500);"#
+    } else {
+        ""
+    }
+}
+
+fn close_tab_tag(config: &OauthConfig) -> String {
+    match close_tab_script(config) {
+        "" => String::new(),
+        script => format!("<script>{}</script>", script),
+    }
+}
+
+/// Replaces the template variables of a redirect target with the URL-encoded callback parameters.
+fn render_url_template(target: &str, response: Option<&OauthResponse>) -> String {
+    let value = |value: Option<&Option<String>>| {
+        url::form_urlencoded::byte_serialize(
+            value
+                .and_then(Option::as_deref)
+                .unwrap_or_default()
+                .as_bytes(),
+        )
+        .collect::<String>()
+    };
+    target
+        .replace("{{code}}", &value(response.map(|r| &r.code)))
+        .replace("{{state}}", &value(response.map(|r| &r.state)))
+        .replace("{{error}}", &value(response.map(|r| &r.error)))
+        .replace(
+            "{{error_description}}",
+            &value(response.map(|r| &r.error_description)),
+        )
+}
+
+/// Replaces the template variables of a response page with the HTML-escaped callback parameters.
+fn render_template(page: &str, response: Option<&OauthResponse>) -> String {
+    let value = |value: Option<&Option<String>>| {
+        html_escape(value.and_then(Option::as_deref).unwrap_or_default())
+    };
+    page.replace("{{error}}", &value(response.map(|r| &r.error)))
+        .replace(
+            "{{error_description}}",
+            &value(response.map(|r| &r.error_description)),
+        )
+        .replace("{{state}}", &value(response.map(|r| &r.state)))
+}
+
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Checks the `state` parameter of a callback URL if state verification is enabled.
+fn state_accepted(config: &OauthConfig, url: &str) -> bool {
+    if let Some(expected) = config.state.as_deref() {
+        // Unparseable URLs are left to the handler, like without state verification.
+        if let Ok(response) = OauthResponse::parse(url) {
+            if !secret_matches(expected, response.state.as_deref()) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+fn html_response(status: u16, config: &OauthConfig, body: &str) -> Response {
+    let content_type = config
+        .headers
+        .iter()
+        .flatten()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+        .map_or(HTML_CONTENT_TYPE, |(_, value)| value.as_str());
+    bytes_response(status, config, content_type, body.as_bytes())
+}
+
+fn redirect_response(status: u16, config: &OauthConfig, location: &str) -> Response {
+    bytes_response(status, config, HTML_CONTENT_TYPE, b"").header("Location", location)
+}
+
+/// Creates a response with the headers of the config, except for `Content-Type` which is always set to `content_type`.
+fn bytes_response(status: u16, config: &OauthConfig, content_type: &str, body: &[u8]) -> Response {
+    let mut response = Response::new(status, content_type, body);
+    let headers = config.headers.iter().flatten();
+    if !headers
+        .clone()
+        .any(|(name, _)| name.eq_ignore_ascii_case("Cache-Control"))
+    {
+        response = response.header("Cache-Control", "no-store");
+    }
+    for (name, value) in headers {
+        if !name.eq_ignore_ascii_case("Content-Type") {
+            response = response.header(name, value);
+        }
+    }
+    response
+}
+
+/// Compares the expected and received secrets, like the `state` value, in constant time.
+pub(crate) fn secret_matches(expected: &str, actual: Option<&str>) -> bool {
+    match actual {
+        Some(actual) if actual.len() == expected.len() => {
+            actual
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+        }
+        _ => false,
+    }
+}
+
+/// Rejects header names that aren't valid HTTP tokens and values containing line breaks,
+/// so user-provided headers can't be used to inject additional headers or a different body.
+fn validate_header(name: &str, value: &str) -> Result<(), Error> {
+    let valid_name = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if !valid_name {
+        return Err(Error::InvalidConfig(format!(
+            "Invalid response header name: {:?}",
+            name
+        )));
+    }
+    if name.eq_ignore_ascii_case("content-length") {
+        return Err(Error::InvalidConfig(
+            "The Content-Length response header can't be overridden".to_string(),
+        ));
+    }
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(Error::InvalidConfig(format!(
+            "Invalid value for response header {:?}",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+/// Quotes `s` as a JavaScript string literal that is safe to embed in an inline `<script>`.
+fn js_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '<' => quoted.push_str("\\u003c"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Stops the currently running server behind the provided port without executing the handler.
+///
+/// # Errors
+///
+/// - Returns [`Error::ServerNotFound`] if no server of this process is running on this port.
+pub fn cancel(port: u16) -> Result<(), Error> {
+    // Not holding the lock while waiting, the server thread needs it to exit.
+    let stops: Vec<Arc<StopSignal>> = RUNNING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .filter(|(addr, _)| addr.port() == port)
+        .map(|(_, stop)| stop.clone())
+        .collect();
+    if stops.is_empty() {
+        return Err(Error::ServerNotFound(port));
+    }
+    for stop in &stops {
+        stop.stop();
+    }
+    for stop in &stops {
+        stop.wait_exited();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{start_with_config, ErrorHandler};
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Request {
+        let mut all = vec![("Host".to_string(), "127.0.0.1".to_string())];
+        all.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: all,
+            body: body.to_vec(),
+        }
+    }
+
+    fn peer() -> SocketAddr {
+        (Ipv4Addr::LOCALHOST, 50000).into()
+    }
+
+    /// Sends a raw request to the server on `port` and returns the whole response.
+    fn send(port: u16, request: &str) -> String {
+        let mut conn = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        conn.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        conn.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn detached_server_keeps_running() {
+        let port = start_with_config(OauthConfig::default(), |_| {}).unwrap();
+        let response = send(port, "GET /?code=abc HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        cancel(port).unwrap();
+    }
+
+    #[test]
+    fn exit_path_does_not_stop_the_server() {
+        let port = start_with_config(OauthConfig::default(), |_| {}).unwrap();
+        let response = send(port, "GET /exit HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+        let response = send(port, "GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        cancel(port).unwrap();
+    }
+
+    #[test]
+    fn full_url_is_only_accepted_for_the_callback_path() {
+        let (sender, receiver) = mpsc::channel();
+        let config = OauthConfig {
+            callback_path: Some("/callback".to_string()),
+            secret_path: Some(true),
+            ..OauthConfig::default()
+        };
+        let port = start_with_config(config, move |url| sender.send(url).unwrap()).unwrap();
+
+        let response = send(
+            port,
+            "GET / HTTP/1.1\r\nHost: 127.0.0.1\r\nFull-Url: http://127.0.0.1/?code=evil\r\n\r\n",
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+        let response = send(
+            port,
+            "GET /cb HTTP/1.1\r\nHost: 127.0.0.1\r\nFull-Url: http://127.0.0.1/callback/guess?code=evil\r\n\r\n",
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+            "{}",
+            response
+        );
+        assert!(receiver.try_recv().is_err());
+        cancel(port).unwrap();
+    }
+
+    #[test]
+    fn state_mismatch_is_reported() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let config = OauthConfig {
+            state: Some("expected".to_string()),
+            on_error: Some(ErrorHandler::new(move |err| {
+                let _ = sender.lock().unwrap().send(err.kind());
+            })),
+            ..OauthConfig::default()
+        };
+        let port = start_with_config(config, |_| {}).unwrap();
+        let response = send(
+            port,
+            "GET /?code=abc&state=forged HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n",
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            response
+        );
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Ok("state_mismatch")
+        );
+        cancel(port).unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn stuck_async_handler_times_out() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let config = OauthConfig {
+            handler_timeout: Some(Duration::from_millis(100)),
+            on_error: Some(ErrorHandler::new(move |err| {
+                let _ = sender.lock().unwrap().send(err.kind());
+            })),
+            ..OauthConfig::default()
+        };
+        let server = crate::start_with_async_response(config, |_| std::future::pending()).unwrap();
+        let response = send(
+            server.port(),
+            "GET /cb HTTP/1.1\r\nHost: 127.0.0.1\r\nFull-Url: http://127.0.0.1/?code=abc\r\n\r\n",
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+            "{}",
+            response
+        );
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Ok("handler_failed")
+        );
+    }
+
+    #[test]
+    fn repeated_callbacks_are_gone_unless_allowed() {
+        const CALLBACK: &str =
+            "GET /cb HTTP/1.1\r\nHost: 127.0.0.1\r\nFull-Url: http://127.0.0.1/?code=abc\r\n\r\n";
+
+        for allow_repeated in [false, true] {
+            let (sender, receiver) = mpsc::channel();
+            let config = OauthConfig {
+                persistent: Some(true),
+                allow_repeated_callbacks: Some(allow_repeated),
+                ..OauthConfig::default()
+            };
+            let port = start_with_config(config, move |url| sender.send(url).unwrap()).unwrap();
+
+            let response = send(port, CALLBACK);
+            assert!(
+                response.starts_with("HTTP/1.1 204 No Content\r\n"),
+                "{}",
+                response
+            );
+            let response = send(port, CALLBACK);
+            if allow_repeated {
+                assert!(
+                    response.starts_with("HTTP/1.1 204 No Content\r\n"),
+                    "{}",
+                    response
+                );
+            } else {
+                assert!(
+                    response.starts_with("HTTP/1.1 410 Gone\r\n"),
+                    "{}",
+                    response
+                );
+            }
+            cancel(port).unwrap();
+
+            let handled = receiver.try_iter().count();
+            assert_eq!(handled, if allow_repeated { 2 } else { 1 });
+        }
+    }
+
+    #[test]
+    fn form_post_body_becomes_the_callback_url() {
+        let config = OauthConfig::default();
+        let form = request(
+            "POST",
+            "/",
+            &[("Content-Type", "application/x-www-form-urlencoded")],
+            b"code=abc&state=xyz&user=%7B%22name%22%3A%22a%26b%23c%22%7D&raw=1#2",
+        );
+        match handle_request(&form, peer(), &config) {
+            Outcome::Callback { url, reply, .. } => {
+                assert!(matches!(reply, Reply::Page));
+                let response = OauthResponse::parse(&url).unwrap();
+                assert_eq!(response.code.as_deref(), Some("abc"));
+                assert_eq!(response.state.as_deref(), Some("xyz"));
+                assert_eq!(
+                    response.query.get("user").map(String::as_str),
+                    Some(r#"{"name":"a&b#c"}"#)
+                );
+                // An unencoded `#` doesn't cut off the params as a fragment.
+                assert_eq!(response.query.get("raw").map(String::as_str), Some("1#2"));
+            }
+            _ => panic!("Expected a callback"),
+        }
+
+        let json = request("POST", "/", &[("Content-Type", "application/json")], b"{}");
+        assert!(matches!(
+            handle_request(&json, peer(), &config),
+            Outcome::Respond(response) if response.status == 415
+        ));
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn disallowed_methods_list_the_allowed_ones() {
+        let config = OauthConfig::default();
+        for (method, path, allowed) in [
+            ("PUT", "/", "GET, HEAD, POST, OPTIONS"),
+            ("POST", "/health", "GET, HEAD, OPTIONS"),
+            ("POST", "/cb", "GET, OPTIONS"),
+        ] {
+            match handle_request(&request(method, path, &[], b""), peer(), &config) {
+                Outcome::Respond(response) => {
+                    assert_eq!(response.status, 405, "{} {}", method, path);
+                    assert_eq!(header(&response, "Allow"), Some(allowed));
+                }
+                _ => panic!("Expected a 405 for {} {}", method, path),
+            }
+        }
+    }
+
+    #[test]
+    fn options_requests_are_answered_without_a_callback() {
+        let config = OauthConfig::default();
+        match handle_request(&request("OPTIONS", "/", &[], b""), peer(), &config) {
+            Outcome::Respond(response) => {
+                assert_eq!(response.status, 204);
+                assert_eq!(header(&response, "Allow"), Some("GET, HEAD, POST, OPTIONS"));
+                assert_eq!(header(&response, "Access-Control-Allow-Methods"), None);
+            }
+            _ => panic!("Expected a 204"),
+        }
+        match handle_request(&request("OPTIONS", "/cb", &[], b""), peer(), &config) {
+            Outcome::Respond(response) => {
+                assert_eq!(response.status, 204);
+                assert_eq!(header(&response, "Allow"), Some("GET, OPTIONS"));
+                assert_eq!(
+                    header(&response, "Access-Control-Allow-Methods"),
+                    Some("GET")
+                );
+            }
+            _ => panic!("Expected a 204"),
+        }
+    }
+
+    #[test]
+    fn head_requests_have_no_body() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let port = start_with_config(OauthConfig::default(), move |url| {
+            let _ = sender.send(url);
+        })
+        .unwrap();
+
+        let response = send(port, "HEAD /missing HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .and_then(|length| length.parse::<usize>().ok());
+        // The length of the page a `GET` would get, without sending it.
+        assert!(length.is_some_and(|length| length > 0), "{}", head);
+        assert!(body.is_empty(), "{}", body);
+
+        let response = send(port, "HEAD /?code=abc HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+        cancel(port).unwrap();
+        assert!(
+            receiver.try_recv().is_err(),
+            "HEAD requests don't capture a callback"
+        );
+    }
+
+    #[test]
+    fn oversized_request_is_rejected() {
+        let config = OauthConfig {
+            max_request_size: Some(1024),
+            ..OauthConfig::default()
+        };
+        let port = start_with_config(config, |_| {}).unwrap();
+
+        let long = format!(
+            "GET /?code={} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n",
+            "a".repeat(1500)
+        );
+        let response = send(port, &long);
+        assert!(
+            response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"),
+            "{}",
+            response
+        );
+        // The server keeps listening for the real callback.
+        let response = send(port, "GET /?code=abc HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        cancel(port).unwrap();
+    }
+
+    #[test]
+    fn header_values_with_control_characters_are_rejected() {
+        for value in ["a\r\nSet-Cookie: x=y", "a\rb", "a\nb", "a\0b"] {
+            assert!(
+                matches!(
+                    validate_header("X-Test", value),
+                    Err(Error::InvalidConfig(_))
+                ),
+                "{:?}",
+                value
+            );
+        }
+        assert!(validate_header("X-Test", "no-store, max-age=0").is_ok());
+    }
+
+    #[test]
+    fn invalid_header_names_are_rejected() {
+        for name in ["", "X Test", "X-Test:", "X-Tést", "X-Test\r\n"] {
+            assert!(
+                matches!(validate_header(name, "value"), Err(Error::InvalidConfig(_))),
+                "{:?}",
+                name
+            );
+        }
+        assert!(validate_header("X-Custom_Header.1", "value").is_ok());
+    }
+
+    #[test]
+    fn content_length_header_is_rejected() {
+        assert!(matches!(
+            validate_header("content-length", "0"),
+            Err(Error::InvalidConfig(_))
+        ));
+        let config = OauthConfig {
+            headers: Some(HashMap::from([(
+                "Content-Length".to_string(),
+                "0".to_string(),
+            )])),
+            ..OauthConfig::default()
+        };
+        assert!(matches!(
+            start_with_config(config, |_| {}),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
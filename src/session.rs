@@ -0,0 +1,219 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, PoisonError,
+};
+
+use url::Url;
+
+use crate::{
+    server::secret_matches, start_with_handle, Error, OauthConfig, OauthResponse, ServerHandle,
+};
+
+type UrlHandler = Box<dyn FnMut(String) + Send>;
+
+/// A login that receives the redirect via the localhost server if possible and via a custom URL scheme otherwise,
+/// for example if a managed Windows environment blocks loopback connections.
+///
+/// The handler receives the full URL either way. Register the URL scheme with `tauri-plugin-deep-link`
+/// or a similar plugin and pass the URLs it receives to [`AuthSession::handle_deep_link`].
+/// Use [`AuthSession::redirect_uri`] to build the authorization URL, it points to the transport in use.
+///
+/// Dropping the session stops the localhost server.
+pub struct AuthSession {
+    inner: Arc<Inner>,
+    server: Option<ServerHandle>,
+    deep_link_uri: String,
+    deep_link: Url,
+}
+
+struct Inner {
+    handler: Mutex<UrlHandler>,
+    state: Option<String>,
+    persistent: bool,
+    delivered: AtomicBool,
+    cancelled: AtomicBool,
+}
+
+/// How an [`AuthSession`] receives the redirect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Via the localhost server on this port.
+    Loopback(u16),
+    /// Via the custom URL scheme, see [`AuthSession::handle_deep_link`].
+    DeepLink,
+}
+
+impl AuthSession {
+    /// Starts the localhost server, or falls back to the custom URL scheme if it can't be started.
+    ///
+    /// Because of the unprotected localhost port and since any app can open custom URLs,
+    /// you _must_ verify the URL in the handler function, preferably by setting [`OauthConfig::state`].
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Configuration the server should use, see [`OauthConfig`]. `state` is verified for both transports.
+    /// * `deep_link_uri` - The redirect URI using your app's custom URL scheme, for example `myapp://callback`.
+    /// * `handler` - Closure which will be executed on a successful redirect. It receives the full URL as a String.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if the config is invalid or `deep_link_uri` is not a valid URL.
+    pub fn start<F: FnMut(String) + Send + 'static>(
+        config: OauthConfig,
+        deep_link_uri: impl Into<String>,
+        handler: F,
+    ) -> Result<Self, Error> {
+        let mut session = Self::deep_link(&config, deep_link_uri.into(), handler)?;
+
+        let inner = session.inner.clone();
+        match start_with_handle(config, move |url| {
+            inner.deliver(url);
+        }) {
+            Ok(server) => session.server = Some(server),
+            // Everything else is a configuration error, which would fail the same way for the next login.
+            Err(err @ (Error::Bind(_) | Error::NoFreePort(_))) => {
+                warn!(
+                    "Couldn't start the localhost server, falling back to {}: {}",
+                    session.deep_link_uri, err
+                );
+            }
+            Err(err) => return Err(err),
+        }
+
+        Ok(session)
+    }
+
+    /// Only uses the custom URL scheme, without starting the localhost server.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if `deep_link_uri` is not a valid URL.
+    pub fn start_deep_link<F: FnMut(String) + Send + 'static>(
+        config: &OauthConfig,
+        deep_link_uri: impl Into<String>,
+        handler: F,
+    ) -> Result<Self, Error> {
+        Self::deep_link(config, deep_link_uri.into(), handler)
+    }
+
+    fn deep_link<F: FnMut(String) + Send + 'static>(
+        config: &OauthConfig,
+        deep_link_uri: String,
+        handler: F,
+    ) -> Result<Self, Error> {
+        let deep_link = Url::parse(&deep_link_uri).map_err(|err| {
+            Error::InvalidConfig(format!(
+                "Invalid deep link URI {:?}: {}",
+                deep_link_uri, err
+            ))
+        })?;
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                handler: Mutex::new(Box::new(handler)),
+                state: config.state.clone(),
+                persistent: config.persistent.unwrap_or(false)
+                    && config.allow_repeated_callbacks.unwrap_or(false),
+                delivered: AtomicBool::new(false),
+                cancelled: AtomicBool::new(false),
+            }),
+            server: None,
+            deep_link_uri,
+            deep_link,
+        })
+    }
+
+    /// The transport in use.
+    #[must_use]
+    pub fn transport(&self) -> Transport {
+        match &self.server {
+            Some(server) => Transport::Loopback(server.port()),
+            None => Transport::DeepLink,
+        }
+    }
+
+    /// The redirect URI to use in the authorization URL, see [`ServerHandle::redirect_uri`].
+    #[must_use]
+    pub fn redirect_uri(&self) -> String {
+        match &self.server {
+            Some(server) => server.redirect_uri(),
+            None => self.deep_link_uri.clone(),
+        }
+    }
+
+    /// Passes a URL received via the custom URL scheme to the handler.
+    ///
+    /// This is accepted for both transports, so a `bridge` to the same scheme doesn't break the login
+    /// if the page couldn't reach the localhost server. Unless [`OauthConfig::allow_repeated_callbacks`] is set,
+    /// only the first redirect is passed to the handler, no matter which transport delivered it.
+    ///
+    /// Returns `false` if the URL was ignored because its scheme, host or path differ from the deep link URI,
+    /// its `state` doesn't match or a redirect was already handled.
+    pub fn handle_deep_link(&self, url: &str) -> bool {
+        let parsed = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(_) => return false,
+        };
+        // A trailing slash may be added or removed when the URL is passed to the app.
+        let same_target = parsed.scheme() == self.deep_link.scheme()
+            && parsed.host_str() == self.deep_link.host_str()
+            && parsed.port() == self.deep_link.port()
+            && parsed.path().trim_end_matches('/') == self.deep_link.path().trim_end_matches('/');
+        if !same_target {
+            return false;
+        }
+        if let Some(expected) = &self.inner.state {
+            let state = OauthResponse::parse(url)
+                .ok()
+                .and_then(|response| response.state);
+            if !secret_matches(expected, state.as_deref()) {
+                warn!("Rejected deep link with mismatching state parameter.");
+                return false;
+            }
+        }
+        self.inner.deliver(url.to_string())
+    }
+
+    /// Stops the localhost server, if it's running. Deep links are ignored afterwards.
+    pub fn cancel(&mut self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.server.take();
+    }
+}
+
+impl Inner {
+    /// Executes the handler unless a redirect was already delivered. Returns whether it was executed.
+    fn deliver(&self, url: String) -> bool {
+        if self.cancelled.load(Ordering::SeqCst)
+            || (self.delivered.swap(true, Ordering::SeqCst) && !self.persistent)
+        {
+            return false;
+        }
+        (self.handler.lock().unwrap_or_else(PoisonError::into_inner))(url);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deep_links_must_match_the_uri_exactly() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let session =
+            AuthSession::start_deep_link(&OauthConfig::default(), "myapp://callback", move |url| {
+                sender.send(url).unwrap();
+            })
+            .unwrap();
+
+        assert!(!session.handle_deep_link("myapp://callbackevil/?code=abc"));
+        assert!(!session.handle_deep_link("myapp://callback.attacker/?code=abc"));
+        assert!(!session.handle_deep_link("otherapp://callback?code=abc"));
+        assert!(!session.handle_deep_link("myapp://callback/other?code=abc"));
+        assert!(receiver.try_recv().is_err());
+
+        assert!(session.handle_deep_link("myapp://callback/?code=abc"));
+        assert_eq!(receiver.try_recv().unwrap(), "myapp://callback/?code=abc");
+    }
+}
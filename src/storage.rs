@@ -0,0 +1,90 @@
+use std::io::ErrorKind;
+
+use keyring::Entry;
+
+use crate::{flow::expiry_timestamp, Error, TokenResponse};
+
+/// Persists tokens in the OS keychain (Keychain on macOS, Credential Manager on Windows, Secret Service on Linux)
+/// instead of plaintext files in the app's data directory.
+///
+/// Entries are keyed by provider and account. Windows limits the size of a credential, so very large
+/// token sets, for example with big `id_token`s, may fail to save there.
+#[derive(Debug, Clone)]
+pub struct TokenStore {
+    service: String,
+}
+
+impl TokenStore {
+    /// Creates a store whose entries are prefixed with `service`, usually your app's bundle identifier.
+    #[must_use]
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+
+    /// Saves the tokens of `account` at `provider`, replacing existing ones.
+    ///
+    /// Tokens without an `expires_at` are assumed to be just received, so their expiry is stored as now plus `expires_in`.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::Keyring`] if the keychain is not accessible.
+    pub fn save_tokens(
+        &self,
+        provider: &str,
+        account: &str,
+        tokens: &TokenResponse,
+    ) -> Result<(), Error> {
+        let mut tokens = tokens.clone();
+        if tokens.expires_at.is_none() {
+            tokens.expires_at = tokens.expires_in.map(expiry_timestamp);
+        }
+        let tokens = serde_json::to_string(&tokens).map_err(invalid_entry)?;
+        self.entry(provider, account)?.set_password(&tokens)?;
+        Ok(())
+    }
+
+    /// Loads the tokens of `account` at `provider`. Returns `None` if there are none.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::Keyring`] if the keychain is not accessible.
+    /// - Returns [`Error::Io`] with `ErrorKind::InvalidData` if the stored entry is invalid.
+    pub fn load_tokens(
+        &self,
+        provider: &str,
+        account: &str,
+    ) -> Result<Option<TokenResponse>, Error> {
+        match self.entry(provider, account)?.get_password() {
+            Ok(tokens) => serde_json::from_str(&tokens)
+                .map(Some)
+                .map_err(invalid_entry),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Deletes the tokens of `account` at `provider`. Does nothing if there are none.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::Keyring`] if the keychain is not accessible.
+    pub fn clear_tokens(&self, provider: &str, account: &str) -> Result<(), Error> {
+        match self.entry(provider, account)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn entry(&self, provider: &str, account: &str) -> Result<Entry, Error> {
+        Ok(Entry::new(
+            &format!("{}.oauth.{}", self.service, provider),
+            account,
+        )?)
+    }
+}
+
+fn invalid_entry(err: serde_json::Error) -> Error {
+    std::io::Error::new(ErrorKind::InvalidData, err).into()
+}
@@ -0,0 +1,396 @@
+//! A fake OAuth provider for integration tests of login flows, without real provider credentials.
+//!
+//! [`MockProvider`] serves an authorization and a token endpoint on localhost. [`MockProvider::authorize`]
+//! plays the part of the browser: It requests the authorization URL and delivers the provider's redirect
+//! to the plugin's localhost server, the same way the response page would. Pass the endpoints to
+//! [`OauthFlow::new`](crate::OauthFlow::new), call `authorize` with the flow's authorization URL
+//! and await [`PendingAuthorization::finish`](crate::PendingAuthorization::finish) as usual.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::OsRng, RngCore};
+use url::Url;
+
+use crate::{
+    http::{read_request, Request, Response},
+    pkce, Error,
+};
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A fake provider running on `127.0.0.1` until it's dropped.
+///
+/// Authorization requests are granted right away by redirecting to their `redirect_uri` with a fresh `code`
+/// and the request's `state`. The token endpoint checks the `code`, the `redirect_uri` and the PKCE
+/// `code_verifier` like a real provider and answers with the configured tokens.
+pub struct MockProvider {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    stopped: AtomicBool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    denial: Option<(String, Option<String>)>,
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+    grants: HashMap<String, Grant>,
+    token_requests: Vec<HashMap<String, String>>,
+}
+
+/// An issued authorization code.
+struct Grant {
+    redirect_uri: String,
+    code_challenge: Option<(String, String)>,
+}
+
+impl MockProvider {
+    /// Starts the provider on a free port.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::Bind`] if the server couldn't be started.
+    pub fn start() -> Result<Self, Error> {
+        let listener =
+            TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).map_err(Error::Bind)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            stopped: AtomicBool::new(false),
+            state: Mutex::new(State {
+                access_token: "mock-access-token".to_string(),
+                refresh_token: Some("mock-refresh-token".to_string()),
+                expires_in: Some(3600),
+                ..State::default()
+            }),
+        });
+
+        let server = shared.clone();
+        let thread = thread::spawn(move || {
+            for conn in listener.incoming() {
+                if server.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                match conn {
+                    Ok(mut conn) => {
+                        let _ = conn.set_read_timeout(Some(TIMEOUT));
+                        let response =
+                            match read_request(&mut conn, 64 * 1024, Instant::now() + TIMEOUT) {
+                                Ok(Some(request)) => server.handle(&request),
+                                Ok(None) => continue,
+                                Err(err) => json_error(400, "invalid_request", &err.to_string()),
+                            };
+                        let _ = response.write_to(&mut conn);
+                    }
+                    Err(err) => warn!("Mock provider failed to accept a connection: {}", err),
+                }
+            }
+        });
+
+        Ok(Self {
+            addr,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// The URL of the authorization endpoint.
+    #[must_use]
+    pub fn authorization_endpoint(&self) -> String {
+        format!("http://{}/authorize", self.addr)
+    }
+
+    /// The URL of the token endpoint.
+    #[must_use]
+    pub fn token_endpoint(&self) -> String {
+        format!("http://{}/token", self.addr)
+    }
+
+    /// Sets the tokens returned by the token endpoint.
+    ///
+    /// Default: `mock-access-token` and `mock-refresh-token`, expiring in an hour.
+    pub fn set_tokens(
+        &self,
+        access_token: impl Into<String>,
+        refresh_token: Option<String>,
+        expires_in: Option<u64>,
+    ) {
+        let mut state = self.shared.lock();
+        state.access_token = access_token.into();
+        state.refresh_token = refresh_token;
+        state.expires_in = expires_in;
+    }
+
+    /// Answers the following authorization requests with this error instead of a code,
+    /// for example `access_denied` to simulate a user who declined the login. Pass `None` to grant them again.
+    pub fn deny(&self, error: Option<(&str, Option<&str>)>) {
+        self.shared.lock().denial = error
+            .map(|(error, description)| (error.to_string(), description.map(ToString::to_string)));
+    }
+
+    /// The form parameters of all requests the token endpoint received so far.
+    #[must_use]
+    pub fn token_requests(&self) -> Vec<HashMap<String, String>> {
+        self.shared.lock().token_requests.clone()
+    }
+
+    /// Requests `authorization_url` like the user's browser would and delivers the redirect to the
+    /// plugin's localhost server with [`deliver_redirect`]. Returns the URL of the redirect.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidUrl`] if `authorization_url` is not a valid URL.
+    /// - Returns [`Error::InvalidResponse`] if the provider didn't redirect, for example because `redirect_uri` is missing,
+    ///   or the localhost server didn't accept the callback.
+    /// - Returns [`Error::Io`] if one of the servers couldn't be reached.
+    pub fn authorize(&self, authorization_url: &str) -> Result<String, Error> {
+        let (status, headers, body) = get(&Url::parse(authorization_url)?, &[])?;
+        let location = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Location"))
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| {
+                Error::InvalidResponse(format!(
+                    "The provider answered with {} instead of a redirect: {}",
+                    status,
+                    String::from_utf8_lossy(&body)
+                ))
+            })?;
+        deliver_redirect(&location)?;
+        Ok(location)
+    }
+}
+
+impl Drop for MockProvider {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        // Wakes up the blocking `accept`.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        let url = match Url::parse(&format!("http://localhost{}", request.path)) {
+            Ok(url) => url,
+            Err(err) => return json_error(400, "invalid_request", &err.to_string()),
+        };
+        match (request.method.as_str(), url.path()) {
+            ("GET", "/authorize") => self.authorize(&url),
+            ("POST", "/token") => self.token(request),
+            _ => Response::new(404, "text/plain", "Not found"),
+        }
+    }
+
+    fn authorize(&self, url: &Url) -> Response {
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let redirect_uri = match params.get("redirect_uri").map(|uri| Url::parse(uri)) {
+            Some(Ok(redirect_uri)) => redirect_uri,
+            _ => return Response::new(400, "text/plain", "Missing or invalid redirect_uri"),
+        };
+
+        let mut state = self.lock();
+        let mut location = redirect_uri.clone();
+        {
+            let mut query = location.query_pairs_mut();
+            match &state.denial {
+                Some((error, description)) => {
+                    query.append_pair("error", error);
+                    if let Some(description) = description {
+                        query.append_pair("error_description", description);
+                    }
+                }
+                None => {
+                    let mut bytes = [0u8; 16];
+                    OsRng.fill_bytes(&mut bytes);
+                    let code = format!("mock-code-{:x}", u128::from_le_bytes(bytes));
+                    query.append_pair("code", &code);
+                    state.grants.insert(
+                        code,
+                        Grant {
+                            // Compared as sent, like providers do.
+                            redirect_uri: params["redirect_uri"].clone(),
+                            code_challenge: params.get("code_challenge").map(|challenge| {
+                                (
+                                    challenge.clone(),
+                                    params
+                                        .get("code_challenge_method")
+                                        .cloned()
+                                        .unwrap_or_else(|| "plain".to_string()),
+                                )
+                            }),
+                        },
+                    );
+                }
+            }
+            if let Some(state) = params.get("state") {
+                query.append_pair("state", state);
+            }
+        }
+
+        Response::new(302, "text/plain", "").header("Location", location.as_str())
+    }
+
+    fn token(&self, request: &Request) -> Response {
+        let form: HashMap<String, String> = url::form_urlencoded::parse(&request.body)
+            .into_owned()
+            .collect();
+        let mut state = self.lock();
+        state.token_requests.push(form.clone());
+
+        let param = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+        match param("grant_type") {
+            "authorization_code" => {
+                let grant = match state.grants.remove(param("code")) {
+                    Some(grant) => grant,
+                    None => {
+                        return json_error(400, "invalid_grant", "Unknown or already used code")
+                    }
+                };
+                if grant.redirect_uri != param("redirect_uri") {
+                    return json_error(400, "invalid_grant", "redirect_uri doesn't match");
+                }
+                if let Some((challenge, method)) = &grant.code_challenge {
+                    let verifier = param("code_verifier");
+                    let computed = match method.as_str() {
+                        "S256" => pkce::challenge(verifier),
+                        _ => verifier.to_string(),
+                    };
+                    if &computed != challenge {
+                        return json_error(400, "invalid_grant", "code_verifier doesn't match");
+                    }
+                }
+            }
+            "refresh_token" => {
+                if state.refresh_token.as_deref() != Some(param("refresh_token")) {
+                    return json_error(400, "invalid_grant", "Unknown refresh token");
+                }
+            }
+            _ => return json_error(400, "unsupported_grant_type", param("grant_type")),
+        }
+
+        let body = serde_json::json!({
+            "access_token": state.access_token,
+            "token_type": "Bearer",
+            "expires_in": state.expires_in,
+            "refresh_token": state.refresh_token,
+        });
+        Response::new(200, JSON_CONTENT_TYPE, body.to_string())
+    }
+}
+
+/// Delivers a redirect to the plugin's localhost server like the user's browser would, including the `/cb`
+/// request of the response page, which also sends the fragment. Use this to test handlers without [`MockProvider`].
+///
+/// # Errors
+///
+/// - Returns [`Error::InvalidUrl`] if `redirect_url` is not a valid URL.
+/// - Returns [`Error::InvalidResponse`] if the server answered with an error status code.
+/// - Returns [`Error::Io`] if the server couldn't be reached.
+pub fn deliver_redirect(redirect_url: &str) -> Result<(), Error> {
+    let url = Url::parse(redirect_url)?;
+    let (mut status, _, body) = get(&url, &[])?;
+    // The page captures the callback, including the fragment, with a request to `/cb`.
+    if status == 200 && String::from_utf8_lossy(&body).contains(r#"fetch("/cb""#) {
+        let mut cb = url.clone();
+        cb.set_path("/cb");
+        cb.set_query(None);
+        cb.set_fragment(None);
+        status = get(&cb, &[("Full-Url", redirect_url)])?.0;
+    }
+    if status >= 400 {
+        return Err(Error::InvalidResponse(format!(
+            "The localhost server answered with {}",
+            status
+        )));
+    }
+    Ok(())
+}
+
+type RawResponse = (u16, Vec<(String, String)>, Vec<u8>);
+
+/// A minimal HTTP/1.1 GET request. Only plain `http` URLs are supported.
+fn get(url: &Url, headers: &[(&str, &str)]) -> Result<RawResponse, Error> {
+    if url.scheme() != "http" {
+        return Err(Error::InvalidResponse(format!(
+            "Only http URLs are supported, got {}",
+            url
+        )));
+    }
+    let host = url.host_str().unwrap_or("localhost");
+    let port = url.port_or_known_default().unwrap_or(80);
+    let mut conn = TcpStream::connect((host, port))?;
+    conn.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\n",
+        path, host, port
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    conn.write_all(request.as_bytes())?;
+
+    let mut raw = Vec::new();
+    conn.read_to_end(&mut raw)?;
+    let mut parsed_headers = [httparse::EMPTY_HEADER; 32];
+    let mut response = httparse::Response::new(&mut parsed_headers);
+    let header_len = match response.parse(&raw) {
+        Ok(httparse::Status::Complete(header_len)) => header_len,
+        Ok(httparse::Status::Partial) => {
+            return Err(Error::InvalidResponse(
+                "Incomplete response headers".to_string(),
+            ))
+        }
+        Err(err) => return Err(Error::InvalidResponse(err.to_string())),
+    };
+
+    Ok((
+        response.code.unwrap_or_default(),
+        response
+            .headers
+            .iter()
+            .map(|header| {
+                (
+                    header.name.to_string(),
+                    String::from_utf8_lossy(header.value).to_string(),
+                )
+            })
+            .collect(),
+        raw[header_len..].to_vec(),
+    ))
+}
+
+fn json_error(status: u16, error: &str, description: &str) -> Response {
+    let body = serde_json::json!({ "error": error, "error_description": description });
+    Response::new(status, JSON_CONTENT_TYPE, body.to_string())
+}
@@ -0,0 +1,67 @@
+use std::{net::IpAddr, net::TcpStream, sync::Arc};
+
+use rcgen::CertifiedKey;
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+use sha2::{Digest, Sha256};
+
+use crate::server::Connection;
+
+/// TLS config with an ephemeral self-signed certificate, valid for `localhost` and the bound address.
+pub(crate) struct ServerTls {
+    config: Arc<ServerConfig>,
+    fingerprint: String,
+}
+
+impl ServerTls {
+    pub(crate) fn new(ip: IpAddr) -> Result<Self, std::io::Error> {
+        let mut names = vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+            "::1".to_string(),
+        ];
+        if !ip.is_unspecified() && !names.contains(&ip.to_string()) {
+            names.push(ip.to_string());
+        }
+
+        let CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(names).map_err(tls_error)?;
+        let cert: CertificateDer<'static> = cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+
+        let fingerprint = Sha256::digest(&cert)
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(":");
+
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(tls_error)?
+                .with_no_client_auth()
+                .with_single_cert(vec![cert], key)
+                .map_err(tls_error)?;
+
+        Ok(Self {
+            config: Arc::new(config),
+            fingerprint,
+        })
+    }
+
+    pub(crate) fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// Wraps an accepted connection. The handshake happens on the first read.
+    pub(crate) fn accept(&self, conn: TcpStream) -> Result<Connection, std::io::Error> {
+        let tls = ServerConnection::new(self.config.clone()).map_err(tls_error)?;
+        Ok(Connection::Tls(Box::new(StreamOwned::new(tls, conn))))
+    }
+}
+
+fn tls_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, err)
+}
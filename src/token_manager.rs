@@ -0,0 +1,361 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tauri::{async_runtime::Mutex, AppHandle, Manager, Runtime};
+use url::Url;
+
+#[cfg(feature = "storage")]
+use crate::TokenStore;
+use crate::{
+    authorization::parse_endpoint, flow::request_token, providers::Provider, AccessToken, Error,
+    TokenResponse,
+};
+
+type EventListener = Box<dyn Fn(&TokenEvent) + Send + Sync>;
+
+/// Keeps a token set up to date by refreshing the access token shortly before it expires.
+///
+/// Refreshes are serialized, so concurrent calls to [`TokenManager::get_access_token`] only refresh once.
+pub struct TokenManager {
+    provider: Option<String>,
+    client_id: String,
+    client_secret: Option<String>,
+    token_endpoint: Url,
+    margin: Duration,
+    tokens: Mutex<Tokens>,
+    listeners: Vec<EventListener>,
+    /// The store, provider and account set via [`TokenManager::store`].
+    #[cfg(feature = "storage")]
+    store: Option<(TokenStore, String, String)>,
+}
+
+struct Tokens {
+    // `None` after a logout or once the session expired.
+    tokens: Option<TokenResponse>,
+    expires_at: Option<SystemTime>,
+}
+
+impl Tokens {
+    /// Whether the access token expired. Tokens without a known expiry never do.
+    fn expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| SystemTime::now() >= expires_at)
+    }
+}
+
+/// Payload of the `oauth://token-refreshed` event. It doesn't contain the tokens themselves.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TokenRefreshed {
+    /// The name set via [`TokenManager::provider`].
+    pub provider: Option<String>,
+    /// When the new access token expires, as a unix timestamp in seconds.
+    pub expires_at: Option<u64>,
+}
+
+/// Payload of the `oauth://session-expired` event, emitted when the tokens can't be refreshed anymore
+/// and the user has to log in again.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SessionExpired {
+    /// The name set via [`TokenManager::provider`].
+    pub provider: Option<String>,
+    /// Why the refresh failed: the token endpoint's `error`, like `invalid_grant`, or `no_refresh_token`.
+    pub reason: String,
+}
+
+/// Payload of the `oauth://logged-out` event, emitted after [`TokenManager::logout`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LoggedOut {
+    /// The name set via [`TokenManager::provider`].
+    pub provider: Option<String>,
+}
+
+/// A change of the token set of a [`TokenManager`], see [`TokenManager::on_event`].
+#[derive(Debug, Clone)]
+pub enum TokenEvent {
+    /// The access token was refreshed.
+    Refreshed(TokenRefreshed),
+    /// The refresh token was rejected or is missing.
+    SessionExpired(SessionExpired),
+    /// The tokens were discarded via [`TokenManager::logout`].
+    LoggedOut(LoggedOut),
+}
+
+impl TokenEvent {
+    /// The name of the event emitted by [`TokenManager::emit_to`], like `oauth://token-refreshed`.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenEvent::Refreshed(_) => "oauth://token-refreshed",
+            TokenEvent::SessionExpired(_) => "oauth://session-expired",
+            TokenEvent::LoggedOut(_) => "oauth://logged-out",
+        }
+    }
+}
+
+impl TokenManager {
+    /// Creates a manager for a token set, for example one loaded from a [`TokenStore`](crate::TokenStore).
+    /// The expiry is taken from `expires_at`, or from `expires_in` relative to now if it isn't set.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if `token_endpoint` is not a valid URL.
+    pub fn new(
+        client_id: impl Into<String>,
+        token_endpoint: &str,
+        tokens: TokenResponse,
+    ) -> Result<Self, Error> {
+        let expires_at = expires_at(&tokens);
+        Ok(Self {
+            provider: None,
+            client_id: client_id.into(),
+            client_secret: None,
+            token_endpoint: parse_endpoint(token_endpoint)?,
+            margin: Duration::from_secs(60),
+            tokens: Mutex::new(Tokens {
+                tokens: Some(tokens),
+                expires_at,
+            }),
+            listeners: Vec::new(),
+            #[cfg(feature = "storage")]
+            store: None,
+        })
+    }
+
+    /// Creates a manager using the token endpoint of a [`Provider`]. The provider's name is used in the events.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if the provider's token endpoint is not a valid URL.
+    pub fn from_provider(
+        provider: &Provider,
+        client_id: impl Into<String>,
+        tokens: TokenResponse,
+    ) -> Result<Self, Error> {
+        Ok(Self::new(client_id, provider.token_endpoint, tokens)?.provider(provider.name))
+    }
+
+    /// Sets the name of the provider included in the events, so the UI can tell which login expired.
+    ///
+    /// Default: `None`, or the name of the [`Provider`] for [`TokenManager::from_provider`].
+    #[must_use]
+    pub fn provider(mut self, provider: impl Into<String>) -> Self {
+        self.provider = Some(provider.into());
+        self
+    }
+
+    /// Sets the client secret, for providers that require one for refreshes.
+    #[must_use]
+    pub fn client_secret(mut self, client_secret: impl Into<String>) -> Self {
+        self.client_secret = Some(client_secret.into());
+        self
+    }
+
+    /// Sets how long before its expiry the access token is refreshed.
+    ///
+    /// Default: 60 seconds.
+    #[must_use]
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    /// Overrides the expiry of the current access token.
+    #[must_use]
+    pub fn expires_at(mut self, expires_at: SystemTime) -> Self {
+        self.tokens.get_mut().expires_at = Some(expires_at);
+        self
+    }
+
+    /// Keeps the tokens of `account` at `provider` in `store` up to date: Refreshed tokens are saved
+    /// and the entry is deleted once the session expired.
+    #[cfg(feature = "storage")]
+    #[must_use]
+    pub fn store(
+        mut self,
+        store: TokenStore,
+        provider: impl Into<String>,
+        account: impl Into<String>,
+    ) -> Self {
+        self.store = Some((store, provider.into(), account.into()));
+        self
+    }
+
+    /// Executes `listener` after every successful refresh.
+    #[must_use]
+    pub fn on_refresh<F: Fn(&TokenRefreshed) + Send + Sync + 'static>(self, listener: F) -> Self {
+        self.on_event(move |event| {
+            if let TokenEvent::Refreshed(payload) = event {
+                listener(payload);
+            }
+        })
+    }
+
+    /// Executes `listener` after every refresh, expiry of the session and logout.
+    #[must_use]
+    pub fn on_event<F: Fn(&TokenEvent) + Send + Sync + 'static>(mut self, listener: F) -> Self {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+
+    /// Emits the `oauth://token-refreshed`, `oauth://session-expired` and `oauth://logged-out` events to all windows,
+    /// with a [`TokenRefreshed`], [`SessionExpired`] or [`LoggedOut`] payload.
+    #[must_use]
+    pub fn emit_to<R: Runtime>(self, app: AppHandle<R>) -> Self {
+        self.on_event(move |event| {
+            let emitted = match event {
+                TokenEvent::Refreshed(payload) => app.emit_all(event.name(), payload.clone()),
+                TokenEvent::SessionExpired(payload) => app.emit_all(event.name(), payload.clone()),
+                TokenEvent::LoggedOut(payload) => app.emit_all(event.name(), payload.clone()),
+            };
+            if let Err(emit_err) = emitted {
+                error!("Error emitting {} event: {}", event.name(), emit_err);
+            }
+        })
+    }
+
+    /// Returns a valid access token, refreshing it first if it expires within the refresh margin.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::NoRefreshToken`] if the token expired and there is no refresh token,
+    ///   after the session expired or after [`TokenManager::logout`].
+    /// - Returns [`Error::TokenRequest`] if the refresh failed, for example because the refresh token was revoked.
+    pub async fn get_access_token(&self) -> Result<AccessToken, Error> {
+        let mut tokens = self.tokens.lock().await;
+        let expiring = tokens
+            .expires_at
+            .is_some_and(|expires_at| SystemTime::now() + self.margin >= expires_at);
+        if expiring {
+            match self.refresh_locked(&mut tokens).await {
+                // Without a refresh token the current access token is still used until it actually expired.
+                Err(Error::NoRefreshToken) if tokens.tokens.is_some() => {}
+                result => result?,
+            }
+        }
+
+        tokens
+            .tokens
+            .as_ref()
+            .map(|tokens| tokens.access_token.clone())
+            .ok_or(Error::NoRefreshToken)
+    }
+
+    /// Refreshes the access token now, regardless of its expiry.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::NoRefreshToken`] if there is no refresh token, or after [`TokenManager::logout`].
+    ///   The tokens are only discarded, and the session reported as expired, if the access token expired too.
+    /// - Returns [`Error::TokenRequest`], [`Error::Http`] or [`Error::InvalidResponse`] if the refresh failed.
+    pub async fn refresh(&self) -> Result<(), Error> {
+        let mut tokens = self.tokens.lock().await;
+        self.refresh_locked(&mut tokens).await
+    }
+
+    /// A copy of the current token set, `None` after the session expired or after [`TokenManager::logout`].
+    pub async fn tokens(&self) -> Option<TokenResponse> {
+        self.tokens.lock().await.tokens.clone()
+    }
+
+    /// Discards the tokens and emits the `oauth://logged-out` event.
+    ///
+    /// This doesn't revoke the tokens at the provider or remove them from a [`TokenStore`](crate::TokenStore).
+    pub async fn logout(&self) {
+        let mut tokens = self.tokens.lock().await;
+        tokens.tokens = None;
+        tokens.expires_at = None;
+        self.emit(&TokenEvent::LoggedOut(LoggedOut {
+            provider: self.provider.clone(),
+        }));
+    }
+
+    async fn refresh_locked(&self, tokens: &mut Tokens) -> Result<(), Error> {
+        let result = self.request_refresh(tokens).await;
+        let reason = match &result {
+            Err(Error::TokenRequest { error, .. }) if error == "invalid_grant" => {
+                Some(error.clone())
+            }
+            // Only once the access token expired, a manual refresh of a valid one keeps the session.
+            // Don't report the same session twice after a logout.
+            Err(Error::NoRefreshToken) if tokens.tokens.is_some() && tokens.expired() => {
+                Some(Error::NoRefreshToken.kind().to_string())
+            }
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            // The refresh token is dead, so the next call doesn't retry it and report the session again.
+            tokens.tokens = None;
+            tokens.expires_at = None;
+            #[cfg(feature = "storage")]
+            if let Some((store, provider, account)) = &self.store {
+                if let Err(err) = store.clear_tokens(provider, account) {
+                    error!(
+                        "Error deleting the expired tokens from the keychain: {}",
+                        err
+                    );
+                }
+            }
+            self.emit(&TokenEvent::SessionExpired(SessionExpired {
+                provider: self.provider.clone(),
+                reason,
+            }));
+        }
+
+        result
+    }
+
+    async fn request_refresh(&self, tokens: &mut Tokens) -> Result<(), Error> {
+        let refresh_token = tokens
+            .tokens
+            .as_ref()
+            .and_then(|tokens| tokens.refresh_token.clone())
+            .ok_or(Error::NoRefreshToken)?;
+
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.secret()),
+            ("client_id", &self.client_id),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let mut refreshed = request_token(&self.token_endpoint, &form).await?;
+        // Providers may omit the refresh token if it didn't change.
+        if refreshed.refresh_token.is_none() {
+            refreshed.refresh_token = Some(refresh_token);
+        }
+        tokens.expires_at = expires_at(&refreshed);
+        #[cfg(feature = "storage")]
+        if let Some((store, provider, account)) = &self.store {
+            if let Err(err) = store.save_tokens(provider, account, &refreshed) {
+                error!("Error saving the refreshed tokens to the keychain: {}", err);
+            }
+        }
+        tokens.tokens = Some(refreshed);
+
+        self.emit(&TokenEvent::Refreshed(TokenRefreshed {
+            provider: self.provider.clone(),
+            expires_at: tokens
+                .expires_at
+                .and_then(|expires_at| expires_at.duration_since(UNIX_EPOCH).ok())
+                .map(|expires_at| expires_at.as_secs()),
+        }));
+
+        Ok(())
+    }
+
+    fn emit(&self, event: &TokenEvent) {
+        for listener in &self.listeners {
+            listener(event);
+        }
+    }
+}
+
+fn expires_at(tokens: &TokenResponse) -> Option<SystemTime> {
+    match tokens.expires_at {
+        Some(expires_at) => Some(UNIX_EPOCH + Duration::from_secs(expires_at)),
+        None => tokens
+            .expires_in
+            .map(|expires_in| SystemTime::now() + Duration::from_secs(expires_in)),
+    }
+}
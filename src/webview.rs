@@ -0,0 +1,189 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tauri::{Manager, Runtime, Window, WindowBuilder, WindowEvent, WindowUrl};
+use url::Url;
+
+use crate::{authorization::parse_endpoint, server::secret_matches, Error, OauthResponse};
+
+type ResultHandler = Box<dyn FnOnce(Result<OauthResponse, Error>) + Send>;
+
+/// Opens the authorization URL in a window of the app instead of the user's browser and captures the
+/// redirect by intercepting the window's navigation, so no localhost server is needed.
+/// Use this if loopback listeners are blocked, for example in some managed enterprise environments.
+///
+/// The `redirect_uri` can be any URL registered with the provider, like `https://example.com/callback`
+/// or a custom scheme. It's never loaded, the navigation is cancelled once it's reached.
+///
+/// Keep in mind that many providers, including Google, reject logins from embedded webviews.
+/// Users also can't use their browser's saved passwords or sessions.
+#[derive(Debug, Clone)]
+pub struct WebviewFlow {
+    authorization_url: Url,
+    redirect_uri: Url,
+    state: Option<String>,
+    label: String,
+    title: String,
+    size: Option<(f64, f64)>,
+}
+
+impl WebviewFlow {
+    /// Creates a flow for the full authorization URL, for example built with [`AuthorizationRequest`](crate::AuthorizationRequest),
+    /// and the `redirect_uri` it contains.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if one of the URLs is not valid.
+    pub fn new(authorization_url: &str, redirect_uri: &str) -> Result<Self, Error> {
+        Ok(Self {
+            authorization_url: parse_endpoint(authorization_url)?,
+            redirect_uri: parse_endpoint(redirect_uri)?,
+            state: None,
+            label: "oauth".to_string(),
+            title: "Login".to_string(),
+            size: None,
+        })
+    }
+
+    /// Sets the expected `state` value. Redirects with another value fail with [`Error::StateMismatch`].
+    ///
+    /// Default: The `state` is not verified.
+    #[must_use]
+    pub fn state(mut self, state: impl Into<String>) -> Self {
+        self.state = Some(state.into());
+        self
+    }
+
+    /// Sets the label of the window. It must be unique among the app's windows.
+    ///
+    /// Default: `oauth`.
+    #[must_use]
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = label.into();
+        self
+    }
+
+    /// Sets the title of the window.
+    ///
+    /// Default: `Login`.
+    #[must_use]
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Sets the inner size of the window in logical pixels.
+    ///
+    /// Default: Tauri's default window size.
+    #[must_use]
+    pub fn inner_size(mut self, width: f64, height: f64) -> Self {
+        self.size = Some((width, height));
+        self
+    }
+
+    /// Opens the window. The handler is executed once with the parsed redirect, after which the window is closed,
+    /// or with [`Error::Cancelled`] if the user closed the window before the redirect.
+    ///
+    /// On Windows, creating windows deadlocks in synchronous commands, call this from an `async` command or another thread.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::InvalidConfig`] if the window couldn't be created, for example because the label is already in use.
+    pub fn open<
+        R: Runtime,
+        M: Manager<R>,
+        F: FnOnce(Result<OauthResponse, Error>) + Send + 'static,
+    >(
+        self,
+        manager: &M,
+        handler: F,
+    ) -> Result<Window<R>, Error> {
+        let handler: Arc<Mutex<Option<ResultHandler>>> =
+            Arc::new(Mutex::new(Some(Box::new(handler))));
+        let window: Arc<Mutex<Option<Window<R>>>> = Arc::new(Mutex::new(None));
+
+        let navigation_handler = handler.clone();
+        let navigation_window = window.clone();
+        let redirect_uri = self.redirect_uri;
+        let state = self.state;
+        let mut builder = WindowBuilder::new(
+            manager,
+            self.label,
+            WindowUrl::External(self.authorization_url),
+        )
+        .title(self.title)
+        .on_navigation(move |url| {
+            if !is_redirect(&url, &redirect_uri) {
+                return true;
+            }
+            let response = OauthResponse::parse(url.as_str())
+                .map_err(Error::from)
+                .and_then(|response| match &state {
+                    Some(expected) if !secret_matches(expected, response.state.as_deref()) => {
+                        Err(Error::StateMismatch)
+                    }
+                    _ => Ok(response),
+                });
+            deliver(&navigation_handler, response);
+            if let Some(window) = navigation_window
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take()
+            {
+                // Closing the window from inside its navigation handler isn't supported on all platforms.
+                std::thread::spawn(move || {
+                    if let Err(err) = window.close() {
+                        error!("Error closing the login window: {}", err);
+                    }
+                });
+            }
+            false
+        });
+        if let Some((width, height)) = self.size {
+            builder = builder.inner_size(width, height);
+        }
+
+        let created = builder.build().map_err(|err| {
+            Error::InvalidConfig(format!("Couldn't create the login window: {}", err))
+        })?;
+        let destroyed_handler = handler.clone();
+        created.on_window_event(move |event| {
+            if let WindowEvent::Destroyed = event {
+                deliver(&destroyed_handler, Err(Error::Cancelled));
+            }
+        });
+        *window.lock().unwrap_or_else(PoisonError::into_inner) = Some(created.clone());
+        // The redirect may have happened before the window was stored.
+        if handler
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none()
+        {
+            if let Some(window) = window.lock().unwrap_or_else(PoisonError::into_inner).take() {
+                if let Err(err) = window.close() {
+                    error!("Error closing the login window: {}", err);
+                }
+            }
+        }
+
+        Ok(created)
+    }
+}
+
+/// Executes the handler unless it was already executed.
+fn deliver(handler: &Mutex<Option<ResultHandler>>, result: Result<OauthResponse, Error>) {
+    let handler = handler
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    if let Some(handler) = handler {
+        handler(result);
+    }
+}
+
+/// Compares everything but the query and fragment, which contain the callback's parameters.
+fn is_redirect(url: &Url, redirect_uri: &Url) -> bool {
+    url.scheme() == redirect_uri.scheme()
+        && url.host_str() == redirect_uri.host_str()
+        && url.port_or_known_default() == redirect_uri.port_or_known_default()
+        && url.path() == redirect_uri.path()
+}
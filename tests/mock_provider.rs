@@ -0,0 +1,212 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+use tauri::async_runtime::block_on;
+use tauri_plugin_oauth::{
+    start_with_async_response,
+    test_utils::{deliver_redirect, MockProvider},
+    Error, HandlerResult, OauthClient, OauthConfig, OauthFlow, TokenEvent, TokenManager,
+};
+
+fn start_flow(provider: &MockProvider) -> tauri_plugin_oauth::PendingAuthorization {
+    OauthFlow::new(
+        "mock-client",
+        &provider.authorization_endpoint(),
+        &provider.token_endpoint(),
+    )
+    .unwrap()
+    .scopes(["profile"])
+    .start()
+    .unwrap()
+}
+
+#[test]
+fn authorization_code_flow() {
+    let provider = MockProvider::start().unwrap();
+    let pending = start_flow(&provider);
+
+    provider
+        .authorize(pending.authorization_url().as_str())
+        .unwrap();
+    let tokens = block_on(pending.finish()).unwrap();
+
+    assert_eq!(tokens.access_token.secret(), "mock-access-token");
+    assert_eq!(
+        tokens.refresh_token.as_ref().map(|token| token.secret()),
+        Some("mock-refresh-token")
+    );
+    let requests = provider.token_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].get("client_id").map(String::as_str),
+        Some("mock-client")
+    );
+    assert!(requests[0].contains_key("code_verifier"));
+    assert!(tokens.expires_at.is_some());
+}
+
+#[test]
+fn stored_tokens_keep_their_expiry() {
+    let provider = MockProvider::start().unwrap();
+    let pending = start_flow(&provider);
+    provider
+        .authorize(pending.authorization_url().as_str())
+        .unwrap();
+    let mut tokens = block_on(pending.finish()).unwrap();
+
+    // Like tokens loaded from the keychain long after they were received.
+    tokens.expires_at = Some(1);
+    provider.set_tokens(
+        "refreshed-access-token",
+        Some("mock-refresh-token".to_string()),
+        Some(3600),
+    );
+    let manager = TokenManager::new("mock-client", &provider.token_endpoint(), tokens).unwrap();
+
+    let access_token = block_on(manager.get_access_token()).unwrap();
+    assert_eq!(access_token.secret(), "refreshed-access-token");
+    assert_eq!(
+        provider.token_requests()[1]
+            .get("grant_type")
+            .map(String::as_str),
+        Some("refresh_token")
+    );
+}
+
+#[test]
+fn denied_authorization() {
+    let provider = MockProvider::start().unwrap();
+    provider.deny(Some(("access_denied", Some("The user declined"))));
+    let pending = start_flow(&provider);
+
+    provider
+        .authorize(pending.authorization_url().as_str())
+        .unwrap();
+
+    match block_on(pending.finish()) {
+        Err(Error::Authorization { error, .. }) => assert_eq!(error, "access_denied"),
+        result => panic!("Expected an authorization error, got {:?}", result),
+    }
+    assert!(provider.token_requests().is_empty());
+}
+
+#[test]
+fn expired_session_is_reported_once() {
+    let provider = MockProvider::start().unwrap();
+    let pending = start_flow(&provider);
+    provider
+        .authorize(pending.authorization_url().as_str())
+        .unwrap();
+    let mut tokens = block_on(pending.finish()).unwrap();
+
+    tokens.expires_at = Some(1);
+    // The provider no longer knows the issued refresh token.
+    provider.set_tokens("mock-access-token", Some("rotated".to_string()), Some(3600));
+    let expired = Arc::new(AtomicUsize::new(0));
+    let counter = expired.clone();
+    let manager = TokenManager::new("mock-client", &provider.token_endpoint(), tokens)
+        .unwrap()
+        .on_event(move |event| {
+            if let TokenEvent::SessionExpired(_) = event {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+    assert!(matches!(
+        block_on(manager.get_access_token()),
+        Err(Error::TokenRequest { error, .. }) if error == "invalid_grant"
+    ));
+    assert!(matches!(
+        block_on(manager.get_access_token()),
+        Err(Error::NoRefreshToken)
+    ));
+    assert_eq!(expired.load(Ordering::SeqCst), 1);
+    assert!(block_on(manager.tokens()).is_none());
+    // The code exchange and the one failed refresh.
+    assert_eq!(provider.token_requests().len(), 2);
+}
+
+#[test]
+fn async_handler_answers_with_the_exchange_result() {
+    let provider = MockProvider::start().unwrap();
+    let client = OauthClient::new("mock-client", &provider.token_endpoint()).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let config = OauthConfig {
+        persistent: Some(true),
+        ..OauthConfig::default()
+    };
+    let server = start_with_async_response(config, move |response| {
+        let client = client.clone();
+        let sender = sender.clone();
+        async move {
+            let redirect_uri = response.url.split('?').next().unwrap_or_default();
+            let code = response.code.as_deref().unwrap_or_default();
+            match client.exchange_code(code, redirect_uri, None).await {
+                Ok(tokens) => {
+                    let _ = sender.send(tokens.access_token.secret().to_string());
+                    HandlerResult::Default
+                }
+                Err(err) => HandlerResult::Error(err.to_string()),
+            }
+        }
+    })
+    .unwrap();
+
+    // A forged code fails the exchange, so the browser gets the error page.
+    let forged = format!("{}?code=forged", server.redirect_uri());
+    assert!(matches!(
+        deliver_redirect(&forged),
+        Err(Error::InvalidResponse(message)) if message.contains("500")
+    ));
+    assert!(receiver.try_recv().is_err());
+
+    let authorization_url = format!(
+        "{}?client_id=mock-client&redirect_uri={}",
+        provider.authorization_endpoint(),
+        url::form_urlencoded::byte_serialize(server.redirect_uri().as_bytes()).collect::<String>()
+    );
+    provider.authorize(&authorization_url).unwrap();
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)).as_deref(),
+        Ok("mock-access-token")
+    );
+    server.stop().unwrap();
+}
+
+#[test]
+fn refreshing_a_valid_token_without_refresh_token_keeps_it() {
+    let provider = MockProvider::start().unwrap();
+    provider.set_tokens("mock-access-token", None, Some(3600));
+    let pending = start_flow(&provider);
+    provider
+        .authorize(pending.authorization_url().as_str())
+        .unwrap();
+    let tokens = block_on(pending.finish()).unwrap();
+    assert!(tokens.refresh_token.is_none());
+
+    let events = Arc::new(AtomicUsize::new(0));
+    let counter = events.clone();
+    let manager = TokenManager::new("mock-client", &provider.token_endpoint(), tokens)
+        .unwrap()
+        .on_event(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+    assert!(matches!(
+        block_on(manager.refresh()),
+        Err(Error::NoRefreshToken)
+    ));
+    assert!(block_on(manager.tokens()).is_some());
+    assert_eq!(
+        block_on(manager.get_access_token()).unwrap().secret(),
+        "mock-access-token"
+    );
+    assert_eq!(events.load(Ordering::SeqCst), 0);
+    // Only the code exchange, the refresh failed without a request.
+    assert_eq!(provider.token_requests().len(), 1);
+}